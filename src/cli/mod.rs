@@ -4,24 +4,107 @@
 
 use std::path::PathBuf;
 use clap::Parser;
+use noodles_sam::alignment::record::data::field::Tag;
 
 #[derive(Parser, Debug)]
 #[command(name = "bam-coverage")]
-#[command(about = "Calculates per-base coverage from a BAM file", long_about = None)]
+#[command(about = "Calculates per-base coverage from a BAM or CRAM file", long_about = None)]
 pub struct Cli {
-    /// Input BAM file
-    #[arg(short, long)]
-    pub input: PathBuf,
+    /// Input BAM or CRAM file(s) ("-" reads BAM from standard input); several inputs
+    /// aligned to the same reference are summed into one coverage track
+    #[arg(short, long, num_args = 1.., required = true)]
+    pub input: Vec<PathBuf>,
+
+    /// Reference FASTA (indexed with samtools faidx), required for CRAM input
+    #[arg(long = "reference")]
+    pub reference: Option<PathBuf>,
+
+    /// Index file for each input, in the same order (BAI, CSI or CRAI); by default looks for
+    /// <input>.bai, <input stem>.bai or <input>.csi (<input>.crai for CRAM)
+    #[arg(long = "index", num_args = 1..)]
+    pub index: Vec<PathBuf>,
 
     /// BED file with regions to include (chrom, start, end)
     #[arg(short = 'b', long = "bed")]
     pub bed: Option<PathBuf>,
 
+    /// Region(s) to include, samtools-style: chr1, chr1:1,000,000-2,000,000 or chr1:500-
+    /// (1-based, inclusive; combined with --bed)
+    #[arg(short = 'r', long = "region", num_args = 1..)]
+    pub regions: Vec<String>,
+
     /// BED file with full chromosome ranges (chrom, start, end for each chromosome)
     #[arg(long = "chrom-bed")]
     pub chrom_bed: Option<PathBuf>,
 
-    /// Number of threads to use (default: half of available cores)
+    /// UCSC chromAlias file mapping contig names between BED files, regions and the alignment
+    /// header (e.g. 1, chr1 and NC_000001.11); a chr prefix difference is matched without it
+    #[arg(long = "chrom-alias")]
+    pub chrom_alias: Option<PathBuf>,
+
+    /// BED file with regions to leave out (e.g. centromeres or a blacklist), subtracted
+    /// from the regions above and from --genome-size
+    #[arg(long = "exclude-bed")]
+    pub exclude_bed: Option<PathBuf>,
+
+    /// Only count alignments with all of these flags set, like samtools -f
+    /// (a number such as 0x2 or flag names such as PROPER_PAIR,READ1)
+    #[arg(long = "include-flags", value_parser = crate::io::filter::parse_flags, default_value = "0")]
+    pub include_flags: u16,
+
+    /// Skip alignments with any of these flags set, like samtools -F
+    /// (a number such as 0x904 or flag names; "0" keeps everything except unmapped reads)
+    #[arg(long = "exclude-flags", value_parser = crate::io::filter::parse_flags, default_value = "UNMAP,SECONDARY,QCFAIL,DUP")]
+    pub exclude_flags: u16,
+
+    /// Skip alignments with a mapping quality below this value, in coverage and read statistics
+    #[arg(long = "min-mapq", default_value_t = 0)]
+    pub min_mapq: u8,
+
+    /// Skip reads shorter than this many bases, in coverage and read statistics
+    #[arg(long = "min-read-length", default_value_t = 0)]
+    pub min_read_length: u32,
+
+    /// Skip alignments with fewer aligned read bases (excluding clipping), in coverage and read statistics
+    #[arg(long = "min-aligned-length", default_value_t = 0)]
+    pub min_aligned_length: u32,
+
+    /// Skip alignments below this percent identity, computed from the NM tag (or cs/MD),
+    /// in coverage and read statistics
+    #[arg(long = "min-identity", value_parser = crate::io::filter::parse_percent, default_value_t = 0.0)]
+    pub min_identity: f64,
+
+    /// Only keep records matching this expression, in coverage and read statistics, e.g.
+    /// "qs > 10 && HP == 1" or "strand == '-' && length > 20000" (fields: flag, mapq, length,
+    /// aligned_length, identity, pos, end, strand, name; other two-character names are tags)
+    #[arg(long = "filter", value_name = "EXPR", value_parser = crate::io::expr::parse_expression)]
+    pub filter_expression: Option<crate::io::expr::FilterExpr>,
+
+    /// Keep this fraction of reads (e.g. 0.375 to turn 40x into 15x), chosen by hashing the
+    /// read name so all alignments of a read stay together; applies to coverage and read statistics
+    #[arg(long = "subsample", value_name = "FRACTION", value_parser = crate::io::filter::parse_fraction)]
+    pub subsample: Option<f64>,
+
+    /// Seed for --subsample; the same seed always selects the same reads
+    #[arg(long = "seed", default_value_t = 0, requires = "subsample")]
+    pub seed: u64,
+
+    /// Count deleted reference bases as covered, like samtools depth -J (reference skips
+    /// such as RNA introns are never counted)
+    #[arg(long = "count-deletions")]
+    pub count_deletions: bool,
+
+    /// Only count aligned bases with at least this base quality, like samtools depth -q
+    #[arg(long = "min-base-qual", default_value_t = 0)]
+    pub min_base_qual: u8,
+
+    /// Count bases covered by both mates of an overlapping read pair once, like mosdepth
+    /// (for paired-end short-read data; off by default)
+    #[arg(long = "dedup-mate-overlaps")]
+    pub dedup_mate_overlaps: bool,
+
+    /// Number of threads for chunk processing and BGZF decompression of whole-file passes
+    /// (default: half of available cores)
     #[arg(short = 't', long = "threads")]
     pub threads: Option<usize>,
 
@@ -65,10 +148,26 @@ pub struct Cli {
     #[arg(long = "cramino-output")]
     pub cramino_output_path: Option<PathBuf>,
     
-    /// Genome size in base pairs (used for coverage calculation in cramino output)
-    #[arg(long = "genome-size")]
+    /// Genome size in base pairs (used for coverage calculation in cramino output); overrides
+    /// the chromosome lengths of --genome or the alignment header
+    #[arg(long = "genome-size", conflicts_with_all = ["genome", "genome_contigs"])]
     pub genome_size: Option<u64>,
+
+    /// Chromosome lengths for the genome size, from a .fai index or a two-column genome file
+    /// (chrom, length); default: the @SQ lines of the alignment header
+    #[arg(long = "genome")]
+    pub genome: Option<PathBuf>,
+
+    /// Only count these contigs in the genome size (comma-separated or repeated, e.g. the
+    /// primary chromosomes), leaving out unplaced contigs, decoys and alternate haplotypes
+    #[arg(long = "genome-contigs", num_args = 1.., value_delimiter = ',')]
+    pub genome_contigs: Vec<String>,
     
+    /// Read the input sequentially in a single pass instead of querying an index
+    /// (used automatically for stdin input `-i -` or when no index is found)
+    #[arg(long = "sweep")]
+    pub sweep: bool,
+
     /// Force streaming mode for memory efficiency (processes chromosomes one at a time)
     #[arg(long = "streaming")]
     pub force_streaming: bool,
@@ -80,4 +179,23 @@ pub struct Cli {
     /// Skip generating plots to save memory (output TSV only)
     #[arg(long = "no-plots")]
     pub skip_all_plots: bool,
+
+    /// Treat each input as a separate sample and write a coverage matrix (one column per
+    /// sample) plus a per-sample summary (<output>.summary.tsv) instead of summing them
+    #[arg(long = "multi-sample")]
+    pub multi_sample: bool,
+
+    /// Window size in bp for the multi-sample matrix (mean depth per window; default: per-base)
+    #[arg(long = "window", requires = "multi_sample")]
+    pub window: Option<u32>,
+
+    /// Split coverage and read stats by the value of this tag (e.g. RG or BC): one coverage
+    /// file and plot set per group (<output stem>.<group>.tsv) and one stats block per group
+    /// (<output>.groups.txt); records without the tag form the group "untagged"
+    #[arg(long = "split-by", value_parser = crate::io::groups::parse_tag, conflicts_with = "multi_sample")]
+    pub split_by: Option<Tag>,
+
+    /// Only process these groups with --split-by (comma-separated or repeated)
+    #[arg(long = "groups", num_args = 1.., value_delimiter = ',', requires = "split_by")]
+    pub groups: Vec<String>,
 }
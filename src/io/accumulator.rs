@@ -0,0 +1,141 @@
+// src/io/accumulator.rs
+// Interval-based coverage accumulators: each alignment costs O(1) instead of one update per base
+
+use std::collections::HashMap;
+
+/// Dense difference array over a fixed half-open window `[start, end)`
+///
+/// Used for chunks, whose size is bounded by `--chunk-size`.
+pub struct DiffArray {
+    start: u32,
+    end: u32,
+    diff: Vec<i32>,
+}
+
+impl DiffArray {
+    /// Create an empty accumulator for positions `start..end`
+    pub fn new(start: u32, end: u32) -> Self {
+        let len = end.saturating_sub(start) as usize;
+        Self {
+            start,
+            end,
+            diff: vec![0; len + 1],
+        }
+    }
+
+    /// Count one alignment covering `start..end`, clipped to the window
+    pub fn add(&mut self, start: u32, end: u32) {
+        let start = start.max(self.start);
+        let end = end.min(self.end);
+        if start >= end {
+            return;
+        }
+        self.diff[(start - self.start) as usize] += 1;
+        self.diff[(end - self.start) as usize] -= 1;
+    }
+
+    /// Add the depth of every covered position to `coverage`
+    pub fn add_into(self, coverage: &mut HashMap<u32, u32>) {
+        let mut depth: i32 = 0;
+        for (offset, delta) in self.diff[..self.diff.len() - 1].iter().enumerate() {
+            depth += delta;
+            if depth > 0 {
+                *coverage.entry(self.start + offset as u32).or_insert(0) += depth as u32;
+            }
+        }
+    }
+}
+
+/// Sparse coverage kept as start/end events
+///
+/// Used where a dense array would span a whole chromosome (sweep and streaming engines).
+#[derive(Default, Clone)]
+pub struct CoverageEvents {
+    events: Vec<(u32, i32)>,
+}
+
+impl CoverageEvents {
+    /// Count one alignment covering `start..end`
+    pub fn add(&mut self, start: u32, end: u32) {
+        if start >= end {
+            return;
+        }
+        self.events.push((start, 1));
+        self.events.push((end, -1));
+    }
+
+    /// Whether no alignment has been counted
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Add the depth of every covered position to `coverage`
+    pub fn add_into(mut self, coverage: &mut HashMap<u32, u32>) {
+        self.events.sort_unstable_by_key(|&(pos, _)| pos);
+
+        let mut depth: i32 = 0;
+        let mut i = 0;
+        while i < self.events.len() {
+            let pos = self.events[i].0;
+            while i < self.events.len() && self.events[i].0 == pos {
+                depth += self.events[i].1;
+                i += 1;
+            }
+            if depth > 0 {
+                // Depth is constant until the next event
+                let next = self.events.get(i).map_or(pos, |&(next, _)| next);
+                for p in pos..next {
+                    *coverage.entry(p).or_insert(0) += depth as u32;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn per_base(intervals: &[(u32, u32)], window: (u32, u32)) -> HashMap<u32, u32> {
+        let mut coverage = HashMap::new();
+        for &(start, end) in intervals {
+            for pos in start.max(window.0)..end.min(window.1) {
+                *coverage.entry(pos).or_insert(0) += 1;
+            }
+        }
+        coverage
+    }
+
+    #[test]
+    fn test_diff_array_matches_per_base_counting() {
+        let intervals = [(5, 20), (10, 12), (0, 8), (18, 40), (12, 12), (30, 31)];
+        let mut diff = DiffArray::new(6, 32);
+        for &(start, end) in &intervals {
+            diff.add(start, end);
+        }
+        let mut coverage = HashMap::new();
+        diff.add_into(&mut coverage);
+        assert_eq!(coverage, per_base(&intervals, (6, 32)));
+    }
+
+    #[test]
+    fn test_events_match_per_base_counting() {
+        let intervals = [(5, 20), (10, 12), (1, 8), (18, 40), (12, 12), (20, 21), (5, 20)];
+        let mut events = CoverageEvents::default();
+        for &(start, end) in &intervals {
+            events.add(start, end);
+        }
+        let mut coverage = HashMap::new();
+        events.add_into(&mut coverage);
+        assert_eq!(coverage, per_base(&intervals, (0, u32::MAX)));
+    }
+
+    #[test]
+    fn test_add_into_accumulates() {
+        let mut coverage = HashMap::from([(3, 2)]);
+        let mut diff = DiffArray::new(0, 10);
+        diff.add(2, 4);
+        diff.add_into(&mut coverage);
+        assert_eq!(coverage, HashMap::from([(2, 1), (3, 3)]));
+    }
+}
@@ -0,0 +1,575 @@
+// src/io/alignment.rs
+// Alignment input for nanocov: opens BAM or CRAM files behind a single reader type
+
+use noodles_bam as bam;
+use noodles_bgzf as bgzf;
+use noodles_core::Region;
+use noodles_cram as cram;
+use noodles_csi::{self as csi, BinningIndex};
+use noodles_fasta as fasta;
+use noodles_sam as sam;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+/// Path that selects standard input instead of a file
+pub const STDIN_PATH: &str = "-";
+
+/// Iterator over alignment records, independent of the container format
+pub type RecordIter<'a> = Box<dyn Iterator<Item = io::Result<Box<dyn sam::alignment::Record + 'a>>> + 'a>;
+
+/// Supported alignment container formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignmentFormat {
+    Bam,
+    Cram,
+}
+
+impl AlignmentFormat {
+    /// Detect the format from the file extension (`.cram` is CRAM, anything else is BAM)
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("cram") => AlignmentFormat::Cram,
+            _ => AlignmentFormat::Bam,
+        }
+    }
+
+    /// Index file names to probe next to the input, in order of preference
+    fn index_candidates(self, path: &Path) -> Vec<PathBuf> {
+        match self {
+            AlignmentFormat::Bam => vec![
+                append_extension(path, "bai"),
+                path.with_extension("bai"),
+                append_extension(path, "csi"),
+            ],
+            AlignmentFormat::Cram => vec![
+                append_extension(path, "crai"),
+                path.with_extension("crai"),
+            ],
+        }
+    }
+}
+
+/// Append an extension to a path (`sample.bam` -> `sample.bam.bai`)
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Byte source for an alignment reader: a file or standard input
+pub enum InputSource {
+    File(File),
+    Stdin(io::Stdin),
+}
+
+impl Read for InputSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            InputSource::File(file) => file.read(buf),
+            InputSource::Stdin(stdin) => stdin.read(buf),
+        }
+    }
+}
+
+impl Seek for InputSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            InputSource::File(file) => file.seek(pos),
+            InputSource::Stdin(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "standard input does not support random access",
+            )),
+        }
+    }
+}
+
+/// Decompressed BGZF stream of a BAM file
+///
+/// Blocks are inflated either on the calling thread or, for whole-file passes, by a pool of workers.
+pub enum BgzfReader {
+    Single(bgzf::io::Reader<BufReader<InputSource>>),
+    Multithreaded(bgzf::MultithreadedReader<BufReader<InputSource>>),
+}
+
+impl Read for BgzfReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BgzfReader::Single(reader) => reader.read(buf),
+            BgzfReader::Multithreaded(reader) => reader.read(buf),
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        match self {
+            BgzfReader::Single(reader) => reader.read_exact(buf),
+            BgzfReader::Multithreaded(reader) => reader.read_exact(buf),
+        }
+    }
+}
+
+impl BufRead for BgzfReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            BgzfReader::Single(reader) => reader.fill_buf(),
+            BgzfReader::Multithreaded(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match self {
+            BgzfReader::Single(reader) => reader.consume(amt),
+            BgzfReader::Multithreaded(reader) => reader.consume(amt),
+        }
+    }
+}
+
+impl bgzf::io::Read for BgzfReader {
+    fn virtual_position(&self) -> bgzf::VirtualPosition {
+        match self {
+            BgzfReader::Single(reader) => reader.virtual_position(),
+            BgzfReader::Multithreaded(reader) => reader.virtual_position(),
+        }
+    }
+}
+
+impl bgzf::io::BufRead for BgzfReader {}
+
+impl bgzf::io::Seek for BgzfReader {
+    fn seek_to_virtual_position(&mut self, pos: bgzf::VirtualPosition) -> io::Result<bgzf::VirtualPosition> {
+        match self {
+            BgzfReader::Single(reader) => reader.seek_to_virtual_position(pos),
+            BgzfReader::Multithreaded(reader) => reader.seek_to_virtual_position(pos),
+        }
+    }
+
+    fn seek_with_index(&mut self, index: &bgzf::gzi::Index, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            BgzfReader::Single(reader) => reader.seek_with_index(index, pos),
+            BgzfReader::Multithreaded(reader) => reader.seek_with_index(index, pos),
+        }
+    }
+}
+
+/// Random access index for an alignment file
+pub enum AlignmentIndex {
+    /// BAI or CSI binning index for BAM input
+    Binning(Box<dyn BinningIndex + Send + Sync>),
+    /// CRAM index
+    Crai(cram::crai::Index),
+}
+
+impl AlignmentIndex {
+    /// Read an index, choosing the parser from the file extension (`.csi`, `.crai`, otherwise BAI)
+    pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let index = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csi") => AlignmentIndex::Binning(Box::new(csi::fs::read(path)?)),
+            Some("crai") => AlignmentIndex::Crai(cram::crai::fs::read(path)?),
+            _ => AlignmentIndex::Binning(Box::new(bam::bai::fs::read(path)?)),
+        };
+        Ok(index)
+    }
+}
+
+/// An alignment file together with everything needed to decode it
+#[derive(Clone)]
+pub struct AlignmentInput {
+    pub path: PathBuf,
+    pub format: AlignmentFormat,
+    index_path: Option<PathBuf>,
+    reference: Option<fasta::Repository>,
+    decompression_threads: usize,
+}
+
+impl AlignmentInput {
+    /// Describe an alignment input, loading the reference FASTA when one is given
+    ///
+    /// CRAM input requires a reference; the FASTA must be indexed (`samtools faidx`).
+    /// Without an explicit index path, the usual index names next to the input are probed.
+    /// A path of `-` reads BAM from standard input, which is never indexed.
+    pub fn new(
+        path: &Path,
+        reference_path: Option<&Path>,
+        index_path: Option<&Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let format = AlignmentFormat::from_path(path);
+
+        let index_path = match index_path {
+            Some(index_path) => {
+                if !index_path.exists() {
+                    return Err(format!("Index file {:?} does not exist", index_path).into());
+                }
+                Some(index_path.to_path_buf())
+            }
+            None if path.as_os_str() == STDIN_PATH => None,
+            None => format
+                .index_candidates(path)
+                .into_iter()
+                .find(|candidate| candidate.exists()),
+        };
+
+        let reference = match reference_path {
+            Some(fasta_path) => {
+                let reader = fasta::io::indexed_reader::Builder::default()
+                    .build_from_path(fasta_path)
+                    .map_err(|e| {
+                        format!(
+                            "Failed to open reference {:?}: {}. Please run 'samtools faidx {:?}' to index it.",
+                            fasta_path, e, fasta_path
+                        )
+                    })?;
+                Some(fasta::Repository::new(
+                    fasta::repository::adapters::IndexedReader::new(reader),
+                ))
+            }
+            None => None,
+        };
+
+        if format == AlignmentFormat::Cram && reference.is_none() {
+            return Err(format!("CRAM input {:?} requires a reference FASTA (--reference)", path).into());
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            format,
+            index_path,
+            reference,
+            decompression_threads: 1,
+        })
+    }
+
+    /// Set the number of BGZF decompression workers used by [`Self::open_sequential`]
+    pub fn with_decompression_threads(mut self, threads: usize) -> Self {
+        self.decompression_threads = threads.max(1);
+        self
+    }
+
+    /// Whether records are read from standard input (single pass only)
+    pub fn is_stdin(&self) -> bool {
+        self.path.as_os_str() == STDIN_PATH
+    }
+
+    /// Index file used for this input, if one was given or found
+    pub fn index_path(&self) -> Option<&Path> {
+        self.index_path.as_deref()
+    }
+
+    /// Index file names that are probed when no index is given explicitly
+    pub fn index_candidates(&self) -> Vec<PathBuf> {
+        self.format.index_candidates(&self.path)
+    }
+
+    /// Read the index associated with this input
+    pub fn read_index(&self) -> Result<AlignmentIndex, Box<dyn std::error::Error>> {
+        let index_path = self
+            .index_path()
+            .ok_or_else(|| format!("No index found for {:?}", self.path))?;
+        AlignmentIndex::read(index_path)
+    }
+
+    /// Read the header and index once so they can be shared by all workers
+    pub fn load_indexed(&self) -> Result<IndexedInput<'_>, Box<dyn std::error::Error>> {
+        let header = self.open()?.read_header()?;
+        let index = self.read_index()?;
+        Ok(IndexedInput {
+            input: self,
+            header,
+            index,
+        })
+    }
+
+    /// Open a new reader positioned at the start of the file
+    ///
+    /// Standard input can only be opened (and read) once.
+    pub fn open(&self) -> Result<AlignmentReader, Box<dyn std::error::Error>> {
+        self.open_with_threads(1)
+    }
+
+    /// Open a reader for whole-file sequential passes
+    ///
+    /// BAM blocks are decompressed by the configured number of worker threads. Readers used for
+    /// region queries should come from [`AlignmentInput::open`]: each seek discards the read-ahead.
+    pub fn open_sequential(&self) -> Result<AlignmentReader, Box<dyn std::error::Error>> {
+        self.open_with_threads(self.decompression_threads)
+    }
+
+    fn open_with_threads(&self, threads: usize) -> Result<AlignmentReader, Box<dyn std::error::Error>> {
+        let source = if self.is_stdin() {
+            InputSource::Stdin(io::stdin())
+        } else {
+            InputSource::File(File::open(&self.path)?)
+        };
+        let reader = match self.format {
+            AlignmentFormat::Bam => {
+                let inner = BufReader::new(source);
+                let bgzf_reader = match NonZeroUsize::new(threads) {
+                    Some(workers) if threads > 1 => {
+                        BgzfReader::Multithreaded(bgzf::MultithreadedReader::with_worker_count(workers, inner))
+                    }
+                    _ => BgzfReader::Single(bgzf::io::Reader::new(inner)),
+                };
+                AlignmentReader::Bam(bam::io::Reader::from(bgzf_reader))
+            }
+            AlignmentFormat::Cram => {
+                let repository = self.reference.clone().unwrap_or_default();
+                AlignmentReader::Cram(
+                    cram::io::reader::Builder::default()
+                        .set_reference_sequence_repository(repository)
+                        .build_from_reader(BufReader::new(source)),
+                )
+            }
+        };
+        Ok(reader)
+    }
+}
+
+/// An input whose header and index have been loaded, shared read-only across threads
+pub struct IndexedInput<'a> {
+    pub input: &'a AlignmentInput,
+    pub header: sam::Header,
+    pub index: AlignmentIndex,
+}
+
+/// Readers opened on first use and reused for every later query
+///
+/// The parallel engines keep one cache per rayon work split (`map_init` runs its init once
+/// per split, so a thread may open the same input more than once). Queries seek to the index
+/// offsets, so the header does not need to be read again.
+pub struct ReaderCache {
+    readers: Vec<Option<AlignmentReader>>,
+}
+
+impl ReaderCache {
+    /// Create an empty cache with room for one reader per input
+    pub fn new(num_inputs: usize) -> Self {
+        Self {
+            readers: (0..num_inputs).map(|_| None).collect(),
+        }
+    }
+
+    /// Get the reader for input `i`, opening it if this thread has not used it yet
+    pub fn get(&mut self, i: usize, input: &AlignmentInput) -> Result<&mut AlignmentReader, Box<dyn std::error::Error>> {
+        if self.readers[i].is_none() {
+            self.readers[i] = Some(input.open()?);
+        }
+        Ok(self.readers[i].as_mut().expect("reader was just opened"))
+    }
+}
+
+/// Check that several inputs can be merged: same reference sequence names and lengths, in order
+///
+/// Standard input can only be read once, so it cannot be combined with other inputs.
+pub fn check_reference_dictionaries(inputs: &[AlignmentInput]) -> Result<(), Box<dyn std::error::Error>> {
+    if inputs.len() < 2 {
+        return Ok(());
+    }
+
+    if inputs.iter().any(|input| input.is_stdin()) {
+        return Err("Standard input cannot be combined with other inputs".into());
+    }
+
+    let dictionary = |input: &AlignmentInput| -> Result<Vec<(String, usize)>, Box<dyn std::error::Error>> {
+        let header = input.open()?.read_header()?;
+        Ok(header
+            .reference_sequences()
+            .iter()
+            .map(|(name, ref_seq)| (name.to_string(), ref_seq.length().get()))
+            .collect())
+    };
+
+    let expected = dictionary(&inputs[0])?;
+    for input in &inputs[1..] {
+        if dictionary(input)? != expected {
+            return Err(format!(
+                "Reference sequences of {:?} do not match those of {:?}; inputs must be aligned to the same reference",
+                input.path, inputs[0].path
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reader over BAM or CRAM records
+pub enum AlignmentReader {
+    Bam(bam::io::Reader<BgzfReader>),
+    Cram(cram::io::Reader<BufReader<InputSource>>),
+}
+
+impl AlignmentReader {
+    /// Read the SAM header; must be called before reading records
+    pub fn read_header(&mut self) -> io::Result<sam::Header> {
+        match self {
+            AlignmentReader::Bam(reader) => reader.read_header(),
+            AlignmentReader::Cram(reader) => reader.read_header(),
+        }
+    }
+
+    /// Iterate over all records in file order
+    pub fn records<'a>(&'a mut self, header: &'a sam::Header) -> RecordIter<'a> {
+        match self {
+            AlignmentReader::Bam(reader) => Box::new(
+                reader
+                    .records()
+                    .map(|result| result.map(|record| Box::new(record) as Box<dyn sam::alignment::Record>)),
+            ),
+            AlignmentReader::Cram(reader) => Box::new(
+                reader
+                    .records(header)
+                    .map(|result| result.map(|record| Box::new(record) as Box<dyn sam::alignment::Record>)),
+            ),
+        }
+    }
+
+    /// Iterate over records overlapping a region using the matching index
+    pub fn query<'a>(
+        &'a mut self,
+        header: &'a sam::Header,
+        index: &'a AlignmentIndex,
+        region: &Region,
+    ) -> Result<RecordIter<'a>, Box<dyn std::error::Error>> {
+        let records: RecordIter<'a> = match (self, index) {
+            (AlignmentReader::Bam(reader), AlignmentIndex::Binning(index)) => Box::new(
+                reader
+                    .query(header, index, region)?
+                    .map(|result| result.map(|record| Box::new(record) as Box<dyn sam::alignment::Record>)),
+            ),
+            (AlignmentReader::Cram(reader), AlignmentIndex::Crai(index)) => Box::new(
+                reader
+                    .query(header, index, region)?
+                    .map(|result| result.map(|record| Box::new(record) as Box<dyn sam::alignment::Record>)),
+            ),
+            _ => return Err("Index type does not match the alignment file format".into()),
+        };
+        Ok(records)
+    }
+
+    /// Iterate over records without an alignment start (unplaced unmapped reads)
+    ///
+    /// Region queries never return these records. Only supported for BAM input.
+    pub fn query_unplaced<'a>(
+        &'a mut self,
+        index: &'a AlignmentIndex,
+    ) -> Result<RecordIter<'a>, Box<dyn std::error::Error>> {
+        let records: RecordIter<'a> = match (self, index) {
+            (AlignmentReader::Bam(reader), AlignmentIndex::Binning(index)) => Box::new(
+                reader
+                    .query_unmapped(index)?
+                    .filter(|result| result.as_ref().map_or(true, |record| record.alignment_start().is_none()))
+                    .map(|result| result.map(|record| Box::new(record) as Box<dyn sam::alignment::Record>)),
+            ),
+            _ => return Err("Querying unplaced reads is only supported for BAM input".into()),
+        };
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(AlignmentFormat::from_path(Path::new("sample.bam")), AlignmentFormat::Bam);
+        assert_eq!(AlignmentFormat::from_path(Path::new("sample.cram")), AlignmentFormat::Cram);
+        assert_eq!(AlignmentFormat::from_path(Path::new("sample.CRAM")), AlignmentFormat::Cram);
+    }
+
+    #[test]
+    fn test_cram_requires_reference() {
+        assert!(AlignmentInput::new(Path::new("sample.cram"), None, None).is_err());
+    }
+
+    #[test]
+    fn test_index_discovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let bam = dir.path().join("sample.bam");
+
+        let input = AlignmentInput::new(&bam, None, None).unwrap();
+        assert_eq!(input.index_path(), None);
+        assert_eq!(
+            input.index_candidates(),
+            vec![
+                dir.path().join("sample.bam.bai"),
+                dir.path().join("sample.bai"),
+                dir.path().join("sample.bam.csi"),
+            ]
+        );
+
+        // A CSI index is picked up when no BAI is present
+        std::fs::write(dir.path().join("sample.bam.csi"), b"").unwrap();
+        let input = AlignmentInput::new(&bam, None, None).unwrap();
+        assert_eq!(input.index_path(), Some(dir.path().join("sample.bam.csi").as_path()));
+
+        // `sample.bai` takes precedence over CSI
+        std::fs::write(dir.path().join("sample.bai"), b"").unwrap();
+        let input = AlignmentInput::new(&bam, None, None).unwrap();
+        assert_eq!(input.index_path(), Some(dir.path().join("sample.bai").as_path()));
+
+        // An explicit index must exist
+        assert!(AlignmentInput::new(&bam, None, Some(&dir.path().join("missing.csi"))).is_err());
+    }
+
+    /// Write a small BAM with `n` 10-base alignments on a 1 kb reference
+    fn write_bam(path: &Path, n: usize) {
+        use sam::alignment::io::Write as _;
+        use sam::alignment::record::cigar::{op::Kind, Op};
+        use sam::alignment::record_buf::{Cigar, Sequence};
+        use sam::header::record::value::{map::ReferenceSequence, Map};
+
+        let header = sam::Header::builder()
+            .add_reference_sequence("chr1", Map::<ReferenceSequence>::new(NonZeroUsize::new(1000).unwrap()))
+            .build();
+        let mut writer = bam::io::Writer::new(File::create(path).unwrap());
+        writer.write_header(&header).unwrap();
+        for i in 0..n {
+            let record = sam::alignment::RecordBuf::builder()
+                .set_reference_sequence_id(0)
+                .set_alignment_start(noodles_core::Position::new(i % 900 + 1).unwrap())
+                .set_cigar(Cigar::from(vec![Op::new(Kind::Match, 10)]))
+                .set_sequence(Sequence::from(b"ACGTACGTAC".to_vec()))
+                .build();
+            writer.write_alignment_record(&header, &record).unwrap();
+        }
+        writer.try_finish().unwrap();
+    }
+
+    #[test]
+    fn test_multithreaded_decompression_reads_all_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let bam = dir.path().join("sample.bam");
+        write_bam(&bam, 20_000);
+
+        let input = AlignmentInput::new(&bam, None, None).unwrap().with_decompression_threads(4);
+        let mut reader = input.open_sequential().unwrap();
+        assert!(matches!(&reader, AlignmentReader::Bam(r) if matches!(r.get_ref(), BgzfReader::Multithreaded(_))));
+
+        let header = reader.read_header().unwrap();
+        let mut count = 0;
+        for result in reader.records(&header) {
+            result.unwrap();
+            count += 1;
+        }
+        assert_eq!(count, 20_000);
+    }
+
+    #[test]
+    fn test_stdin_cannot_be_merged() {
+        let stdin = AlignmentInput::new(Path::new(STDIN_PATH), None, None).unwrap();
+        let file = AlignmentInput::new(Path::new("sample.bam"), None, None).unwrap();
+        assert!(check_reference_dictionaries(std::slice::from_ref(&stdin)).is_ok());
+        assert!(check_reference_dictionaries(&[file, stdin]).is_err());
+    }
+
+    #[test]
+    fn test_stdin_is_unindexed() {
+        let input = AlignmentInput::new(Path::new(STDIN_PATH), None, None).unwrap();
+        assert!(input.is_stdin());
+        assert_eq!(input.format, AlignmentFormat::Bam);
+        assert_eq!(input.index_path(), None);
+    }
+}
@@ -0,0 +1,316 @@
+// src/io/blocks.rs
+// CIGAR walking: which reference positions of an alignment are credited with coverage
+
+use super::subtract_regions;
+use crate::cli::Cli;
+use crate::utils::base_qualities;
+use noodles_sam as sam;
+use std::collections::BTreeMap;
+
+/// Rules for turning an alignment into covered reference intervals
+#[derive(Debug, Clone, Default)]
+pub struct CoverageRules {
+    /// Count deleted reference bases as covered, like `samtools depth -J`
+    count_deletions: bool,
+    /// Only count aligned bases with at least this base quality, like `samtools depth -q`
+    min_base_qual: u8,
+    /// Count bases covered by both mates of a fragment once, like mosdepth
+    dedup_mate_overlaps: bool,
+}
+
+/// Mate start and read name of a pending first mate
+type MateKey = (u32, Vec<u8>);
+
+/// Counted blocks of first mates whose mate starts inside them, by mate start and read name
+///
+/// Holds the state of `--dedup-mate-overlaps` for one coordinate-sorted stream of records
+/// (a chunk, a region query or a sweep). Entries whose mate never came (filtered out or
+/// missing) are dropped once the stream is past the mate start or on another reference
+/// sequence, so they do not pile up over a whole-file sweep.
+#[derive(Debug, Default)]
+pub struct MateOverlaps {
+    pending: BTreeMap<MateKey, Vec<(u32, u32)>>,
+    reference_sequence_id: Option<usize>,
+}
+
+impl MateOverlaps {
+    /// Move on to a record at `start` on `reference_sequence_id`, dropping the entries whose
+    /// mate would have come before it
+    fn advance(&mut self, reference_sequence_id: Option<usize>, start: u32) {
+        if reference_sequence_id != self.reference_sequence_id {
+            self.pending.clear();
+            self.reference_sequence_id = reference_sequence_id;
+        } else if self.pending.first_key_value().is_some_and(|((mate_start, _), _)| *mate_start < start) {
+            self.pending = self.pending.split_off(&(start, Vec::new()));
+        }
+    }
+}
+
+impl CoverageRules {
+    /// Build the rules from the command line options
+    pub fn from_cli(cli: &Cli) -> Self {
+        Self {
+            count_deletions: cli.count_deletions,
+            min_base_qual: cli.min_base_qual,
+            dedup_mate_overlaps: cli.dedup_mate_overlaps,
+        }
+    }
+
+    /// Reference intervals `[start, end)` (1-based) credited by an alignment
+    ///
+    /// Only aligned blocks (M, =, X) count; deletions count with `count_deletions`, and
+    /// reference skips (N) never do. With `min_base_qual`, aligned bases below the threshold
+    /// are left out (deletions have no quality and are kept), and records without base
+    /// qualities credit nothing, like `samtools depth -q`. Adjacent blocks are merged.
+    pub fn blocks(&self, record: &dyn sam::alignment::Record) -> std::io::Result<Vec<(u32, u32)>> {
+        use sam::alignment::record::cigar::op::Kind;
+
+        let mut blocks: Vec<(u32, u32)> = Vec::new();
+        let mut pos = match record.alignment_start().transpose()? {
+            Some(start) => start.get() as u32,
+            None => return Ok(blocks),
+        };
+
+        // Base qualities are only read when filtering on them; a missing QUAL ('*', 0xFF
+        // bytes in BAM) has no base reaching the threshold
+        let quals: Vec<u8> = if self.min_base_qual > 0 {
+            match base_qualities(record)? {
+                Some(quals) => quals,
+                None => return Ok(blocks),
+            }
+        } else {
+            Vec::new()
+        };
+        let mut read_pos = 0;
+
+        for result in record.cigar().iter() {
+            let op = result?;
+            let len = op.len() as u32;
+            match op.kind() {
+                Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch => {
+                    if quals.is_empty() {
+                        push_block(&mut blocks, pos, pos + len);
+                    } else {
+                        for i in 0..len {
+                            let qual = quals.get(read_pos + i as usize).copied().unwrap_or(u8::MAX);
+                            if qual >= self.min_base_qual {
+                                push_block(&mut blocks, pos + i, pos + i + 1);
+                            }
+                        }
+                    }
+                    pos += len;
+                    read_pos += len as usize;
+                }
+                Kind::Deletion => {
+                    if self.count_deletions {
+                        push_block(&mut blocks, pos, pos + len);
+                    }
+                    pos += len;
+                }
+                Kind::Skip => pos += len,
+                Kind::Insertion | Kind::SoftClip => read_pos += len as usize,
+                Kind::HardClip | Kind::Pad => {}
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    /// Blocks counted for a record, without the bases already counted for its mate
+    ///
+    /// With `dedup_mate_overlaps`, the first mate of an overlapping pair (by position) keeps its
+    /// blocks in `mates`, and the second mate only counts the bases outside them. Records
+    /// must be passed in coordinate order; a mate that was filtered out leaves its pair unchanged.
+    pub fn counted_blocks(
+        &self,
+        header: &sam::Header,
+        record: &dyn sam::alignment::Record,
+        mates: &mut MateOverlaps,
+    ) -> std::io::Result<Vec<(u32, u32)>> {
+        let blocks = self.blocks(record)?;
+        if !self.dedup_mate_overlaps || blocks.is_empty() {
+            return Ok(blocks);
+        }
+
+        let reference_sequence_id = record.reference_sequence_id(header).transpose()?;
+        let start = match record.alignment_start().transpose()? {
+            Some(start) => start.get() as u32,
+            None => return Ok(blocks),
+        };
+        mates.advance(reference_sequence_id, start);
+
+        // Only primary alignments of pairs with both mates on the same reference sequence
+        let flags = record.flags()?;
+        if !flags.is_segmented() || flags.is_mate_unmapped() || flags.is_secondary() || flags.is_supplementary() {
+            return Ok(blocks);
+        }
+        let mate_reference_sequence_id = record.mate_reference_sequence_id(header).transpose()?;
+        if reference_sequence_id.is_none() || reference_sequence_id != mate_reference_sequence_id {
+            return Ok(blocks);
+        }
+        let name = match record.name() {
+            Some(name) => name.to_vec(),
+            None => return Ok(blocks),
+        };
+
+        // The first mate was stored under the start of this one
+        if let Some(mate_blocks) = mates.pending.remove(&(start, name.clone())) {
+            return Ok(subtract_regions(&blocks, &mate_blocks));
+        }
+
+        let mate_start = record.mate_alignment_start().transpose()?.map(|start| start.get() as u32);
+        let end = blocks.last().map_or(0, |&(_, end)| end);
+        if let Some(mate_start) = mate_start
+            && mate_start >= start
+            && mate_start < end
+        {
+            mates.pending.insert((mate_start, name), blocks.clone());
+        }
+
+        Ok(blocks)
+    }
+
+    /// `##` lines describing the rules, written at the top of coverage outputs
+    pub fn header_lines(&self) -> Vec<String> {
+        vec![
+            format!("##count_deletions={}", self.count_deletions),
+            format!("##min_base_qual={}", self.min_base_qual),
+            format!("##dedup_mate_overlaps={}", self.dedup_mate_overlaps),
+        ]
+    }
+}
+
+/// Append `[start, end)`, extending the last block when they touch
+fn push_block(blocks: &mut Vec<(u32, u32)>, start: u32, end: u32) {
+    if start >= end {
+        return;
+    }
+    match blocks.last_mut() {
+        Some((_, last_end)) if *last_end == start => *last_end = end,
+        _ => blocks.push((start, end)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sam::alignment::record::cigar::{op::Kind, Op};
+    use sam::alignment::record_buf::Cigar;
+    use sam::alignment::RecordBuf;
+
+    fn record(start: usize, ops: &[(Kind, usize)]) -> RecordBuf {
+        RecordBuf::builder()
+            .set_alignment_start(noodles_core::Position::new(start).unwrap())
+            .set_cigar(Cigar::from(ops.iter().map(|&(kind, len)| Op::new(kind, len)).collect::<Vec<_>>()))
+            .build()
+    }
+
+    #[test]
+    fn test_blocks_skip_deletions_and_introns() {
+        // 2S 5M 1I 3M 2D 4M 100N 5M
+        let record = record(
+            10,
+            &[
+                (Kind::SoftClip, 2),
+                (Kind::Match, 5),
+                (Kind::Insertion, 1),
+                (Kind::Match, 3),
+                (Kind::Deletion, 2),
+                (Kind::Match, 4),
+                (Kind::Skip, 100),
+                (Kind::Match, 5),
+            ],
+        );
+
+        let rules = CoverageRules::default();
+        assert_eq!(rules.blocks(&record).unwrap(), vec![(10, 18), (20, 24), (124, 129)]);
+
+        let rules = CoverageRules { count_deletions: true, ..Default::default() };
+        assert_eq!(rules.blocks(&record).unwrap(), vec![(10, 24), (124, 129)]);
+    }
+
+    #[test]
+    fn test_blocks_without_position() {
+        let record = RecordBuf::builder().build();
+        assert!(CoverageRules::default().blocks(&record).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_blocks_with_min_base_qual() {
+        use sam::alignment::record_buf::QualityScores;
+
+        // 1S 4M 1I 2D 3M with qualities for 9 read bases
+        let mut record = record(
+            100,
+            &[(Kind::SoftClip, 1), (Kind::Match, 4), (Kind::Insertion, 1), (Kind::Deletion, 2), (Kind::Match, 3)],
+        );
+        *record.quality_scores_mut() = QualityScores::from(vec![0, 30, 5, 30, 30, 0, 30, 2, 30]);
+
+        let rules = CoverageRules { min_base_qual: 10, ..Default::default() };
+        assert_eq!(rules.blocks(&record).unwrap(), vec![(100, 101), (102, 104), (106, 107), (108, 109)]);
+
+        // Deletions have no quality and are kept with count_deletions
+        let rules = CoverageRules { count_deletions: true, min_base_qual: 10, ..Default::default() };
+        assert_eq!(rules.blocks(&record).unwrap(), vec![(100, 101), (102, 107), (108, 109)]);
+
+        // Records without base qualities credit nothing: QUAL '*' is empty in SAM and
+        // 0xFF bytes in BAM
+        *record.quality_scores_mut() = QualityScores::default();
+        assert!(rules.blocks(&record).unwrap().is_empty());
+        *record.quality_scores_mut() = QualityScores::from(vec![0xFF; 9]);
+        assert!(rules.blocks(&record).unwrap().is_empty());
+        // and count in full without a threshold
+        assert_eq!(CoverageRules::default().blocks(&record).unwrap(), vec![(100, 104), (106, 109)]);
+    }
+
+    #[test]
+    fn test_dedup_mate_overlaps() {
+        use sam::alignment::record::Flags;
+        use sam::header::record::value::{map::ReferenceSequence, Map};
+        use std::num::NonZeroUsize;
+
+        let header = sam::Header::builder()
+            .add_reference_sequence("chr1", Map::<ReferenceSequence>::new(NonZeroUsize::new(1000).unwrap()))
+            .build();
+        let mate = |name: &str, start: usize, mate_start: usize, flags: Flags| {
+            let mut record = record(start, &[(Kind::Match, 100)]);
+            *record.name_mut() = Some(name.into());
+            *record.flags_mut() = flags;
+            *record.reference_sequence_id_mut() = Some(0);
+            *record.mate_reference_sequence_id_mut() = Some(0);
+            *record.mate_alignment_start_mut() = noodles_core::Position::new(mate_start);
+            record
+        };
+        let first = mate("frag", 100, 150, Flags::SEGMENTED | Flags::FIRST_SEGMENT);
+        let second = mate("frag", 150, 100, Flags::SEGMENTED | Flags::LAST_SEGMENT);
+
+        let rules = CoverageRules { dedup_mate_overlaps: true, ..Default::default() };
+        let mut mates = MateOverlaps::default();
+        assert_eq!(rules.counted_blocks(&header, &first, &mut mates).unwrap(), vec![(100, 200)]);
+        assert_eq!(rules.counted_blocks(&header, &second, &mut mates).unwrap(), vec![(200, 250)]);
+        assert!(mates.pending.is_empty());
+
+        // Without the first mate (e.g. filtered out) the second one counts in full
+        assert_eq!(rules.counted_blocks(&header, &second, &mut MateOverlaps::default()).unwrap(), vec![(150, 250)]);
+
+        // Without the second mate, the first one is dropped once the stream is past its mate
+        // start, or on another reference sequence
+        let mut mates = MateOverlaps::default();
+        rules.counted_blocks(&header, &first, &mut mates).unwrap();
+        rules.counted_blocks(&header, &mate("other", 140, 500, Flags::SEGMENTED), &mut mates).unwrap();
+        assert_eq!(mates.pending.len(), 1);
+        rules.counted_blocks(&header, &mate("other", 151, 500, Flags::SEGMENTED), &mut mates).unwrap();
+        assert!(mates.pending.is_empty());
+
+        rules.counted_blocks(&header, &first, &mut mates).unwrap();
+        let mut unpaired = record(120, &[(Kind::Match, 10)]);
+        *unpaired.reference_sequence_id_mut() = Some(1);
+        rules.counted_blocks(&header, &unpaired, &mut mates).unwrap();
+        assert!(mates.pending.is_empty());
+
+        // Off by default
+        let mut mates = MateOverlaps::default();
+        CoverageRules::default().counted_blocks(&header, &first, &mut mates).unwrap();
+        assert_eq!(CoverageRules::default().counted_blocks(&header, &second, &mut mates).unwrap(), vec![(150, 250)]);
+    }
+}
@@ -0,0 +1,200 @@
+// src/io/contigs.rs
+// Contig name reconciliation between BED files, region strings and the alignment header:
+// `chr` prefixes and UCSC chromAlias files
+
+use crate::cli::Cli;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+/// Regions by contig name, as in a BED file
+type Regions = HashMap<String, Vec<(u32, u32)>>;
+
+/// Contigs of the alignment header and the other names they go by
+#[derive(Debug, Default)]
+pub struct ContigNames {
+    /// Reference sequence lengths by header name
+    lengths: HashMap<String, u32>,
+    /// Names of the same sequence from `--chrom-alias`, by name
+    aliases: HashMap<String, Vec<String>>,
+}
+
+impl ContigNames {
+    /// Header contigs of `header`, with the aliases of `--chrom-alias` if given
+    pub fn new(cli: &Cli, header: &noodles_sam::Header) -> Result<Self, Box<dyn std::error::Error>> {
+        let lengths = header
+            .reference_sequences()
+            .iter()
+            .map(|(name, ref_seq)| (name.to_string(), ref_seq.length().get() as u32))
+            .collect();
+        Self::from_lengths(cli, lengths)
+    }
+
+    /// Contigs with the given lengths (e.g. from a genome file), with the aliases of `--chrom-alias`
+    pub fn from_lengths(cli: &Cli, lengths: HashMap<String, u32>) -> Result<Self, Box<dyn std::error::Error>> {
+        let aliases = match &cli.chrom_alias {
+            Some(path) => parse_chrom_alias(path)?,
+            None => HashMap::new(),
+        };
+        Ok(Self { lengths, aliases })
+    }
+
+    /// Header name for a contig name used elsewhere
+    ///
+    /// Tries the name itself, then its aliases, each as written and with the `chr` prefix
+    /// added or removed (`chrM` also matches `MT`).
+    pub fn resolve(&self, name: &str) -> Option<&str> {
+        let mut candidates = vec![name.to_string()];
+        candidates.extend(self.aliases.get(name).into_iter().flatten().cloned());
+
+        candidates
+            .iter()
+            .flat_map(|candidate| std::iter::once(candidate.clone()).chain(chr_variants(candidate)))
+            .find_map(|candidate| self.lengths.get_key_value(&candidate).map(|(name, _)| name.as_str()))
+    }
+
+    /// Lengths of the header contigs under every name that resolves to them, for checking
+    /// region strings
+    pub fn lengths_with_aliases(&self) -> HashMap<String, u32> {
+        let mut lengths = self.lengths.clone();
+        for (name, &length) in &self.lengths {
+            let mut names: Vec<String> = chr_variants(name);
+            for alias in self.aliases.get(name).into_iter().flatten() {
+                names.push(alias.clone());
+                names.extend(chr_variants(alias));
+            }
+            for alias in names {
+                lengths.entry(alias).or_insert(length);
+            }
+        }
+        lengths
+    }
+
+    /// Rename the contigs of `regions` to header names
+    ///
+    /// Returns the renamed regions (intervals of names resolving to the same contig are
+    /// combined) and the names not found in the header, sorted.
+    pub fn rename_regions(&self, regions: Regions) -> (Regions, Vec<String>) {
+        let mut renamed: Regions = HashMap::new();
+        let mut missing = Vec::new();
+        for (chrom, intervals) in regions {
+            match self.resolve(&chrom) {
+                Some(name) => renamed.entry(name.to_string()).or_default().extend(intervals),
+                None => missing.push(chrom),
+            }
+        }
+        missing.sort();
+        (renamed, missing)
+    }
+
+    /// Name of a header contig for plot labels: a `chr`-style alias where the header uses
+    /// another naming scheme (e.g. `chr1` for `NC_000001.11`)
+    pub fn display_name(&self, chrom: &str) -> String {
+        if chrom.starts_with("chr") {
+            return chrom.to_string();
+        }
+        self.aliases
+            .get(chrom)
+            .and_then(|aliases| aliases.iter().find(|alias| alias.starts_with("chr")))
+            .cloned()
+            .unwrap_or_else(|| chrom.to_string())
+    }
+}
+
+/// The name with the `chr` prefix removed or added
+fn chr_variants(name: &str) -> Vec<String> {
+    match name.strip_prefix("chr") {
+        Some("M") => vec!["M".to_string(), "MT".to_string()],
+        Some(base) => vec![base.to_string()],
+        None if name == "MT" => vec!["chrMT".to_string(), "chrM".to_string()],
+        None => vec![format!("chr{}", name)],
+    }
+}
+
+/// Read a UCSC chromAlias file: names of the same sequence by name
+///
+/// With a `#` header line (`# ucsc  assembly  genbank  refseq`), every column of a line is a
+/// name of the same sequence. Without one, lines are `alias  chrom  source` as in the UCSC
+/// chromAlias table, and the source column is ignored.
+fn parse_chrom_alias(path: &PathBuf) -> Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
+    let file = std::fs::File::open(path).map_err(|e| format!("Failed to open chromAlias file {:?}: {}", path, e))?;
+    let mut aliases: HashMap<String, Vec<String>> = HashMap::new();
+    let mut has_header = false;
+
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            has_header = true;
+            continue;
+        }
+        let columns = if has_header { usize::MAX } else { 2 };
+        let names: Vec<&str> = line.split('\t').take(columns).map(str::trim).filter(|name| !name.is_empty()).collect();
+        for &name in &names {
+            let others = aliases.entry(name.to_string()).or_default();
+            for &other in &names {
+                if other != name && !others.iter().any(|known| known == other) {
+                    others.push(other.to_string());
+                }
+            }
+        }
+    }
+    Ok(aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contig_names(names: &[&str], aliases: &str) -> ContigNames {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chromAlias.txt");
+        std::fs::write(&path, aliases).unwrap();
+        ContigNames {
+            lengths: names.iter().map(|name| (name.to_string(), 1000)).collect(),
+            aliases: parse_chrom_alias(&path).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_chr_prefix() {
+        let contigs = contig_names(&["1", "X", "MT"], "");
+        assert_eq!(contigs.resolve("chr1"), Some("1"));
+        assert_eq!(contigs.resolve("X"), Some("X"));
+        assert_eq!(contigs.resolve("chrM"), Some("MT"));
+        assert_eq!(contigs.resolve("chr2"), None);
+
+        let contigs = contig_names(&["chr1", "chrM"], "");
+        assert_eq!(contigs.resolve("1"), Some("chr1"));
+        assert_eq!(contigs.resolve("MT"), Some("chrM"));
+    }
+
+    #[test]
+    fn test_resolve_chrom_alias_file() {
+        // Header-style file: every column is a name
+        let contigs = contig_names(
+            &["NC_000001.11", "NC_012920.1"],
+            "# ucsc\tassembly\tgenbank\trefseq\nchr1\t1\tCM000663.2\tNC_000001.11\nchrM\tMT\tJ01415.2\tNC_012920.1\n",
+        );
+        assert_eq!(contigs.resolve("chr1"), Some("NC_000001.11"));
+        assert_eq!(contigs.resolve("1"), Some("NC_000001.11"));
+        assert_eq!(contigs.resolve("MT"), Some("NC_012920.1"));
+        assert_eq!(contigs.display_name("NC_000001.11"), "chr1");
+        assert_eq!(contigs.lengths_with_aliases().get("CM000663.2"), Some(&1000));
+
+        // Table-style file: alias, chrom, source
+        let contigs = contig_names(&["chr1"], "NC_000001.11\tchr1\trefseq\n");
+        assert_eq!(contigs.resolve("NC_000001.11"), Some("chr1"));
+        assert_eq!(contigs.resolve("refseq"), None);
+
+        let regions = HashMap::from([
+            ("NC_000001.11".to_string(), vec![(0, 10)]),
+            ("1".to_string(), vec![(20, 30)]),
+            ("chrUn_1".to_string(), vec![(0, 5)]),
+        ]);
+        let (renamed, missing) = contigs.rename_regions(regions);
+        let mut intervals = renamed["chr1"].clone();
+        intervals.sort();
+        assert_eq!(intervals, vec![(0, 10), (20, 30)]);
+        assert_eq!(missing, vec!["chrUn_1".to_string()]);
+    }
+}
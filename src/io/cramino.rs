@@ -1,10 +1,11 @@
 // src/io/cramino.rs
-// Module for generating cramino-like output from BAM files
+// Module for generating cramino-like output from BAM/CRAM files
 
-use crate::utils::ReadStats;
-use noodles_bam as bam;
+use crate::io::alignment::AlignmentInput;
+use crate::io::filter::{FilterCounts, ReadFilter};
+use crate::utils::{mean_base_quality, ReadStats};
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use std::fmt::Write as FmtWrite;
@@ -24,6 +25,8 @@ pub struct CraminoOutput {
     pub mean_length: f64,
     pub path: PathBuf,
     pub creation_time: String,
+    /// Label and count of the alignments removed by each active read-level filter
+    pub filtered: Vec<(String, u64)>,
 }
 
 impl CraminoOutput {
@@ -68,6 +71,7 @@ impl CraminoOutput {
             mean_length: 0.0,
             path,
             creation_time,
+            filtered: Vec::new(),
         }
     }
 
@@ -75,28 +79,6 @@ impl CraminoOutput {
     pub fn from_read_stats(path: PathBuf, read_stats: &ReadStats, total_coverage: f64, genome_size: u64) -> Self {
         let mut result = Self::new_empty(path.clone());
         
-        // Calculate N75
-        // (We assume the ReadStats already has calculated N50 and sorted lengths)
-        let n75 = if let Some(lengths) = &read_stats.lengths {
-            if lengths.is_empty() {
-                0
-            } else {
-                let total: u64 = lengths.iter().map(|&l| l as u64).sum();
-                let mut acc = 0;
-                let mut n75 = 0;
-                for &l in lengths {
-                    acc += l as u64;
-                    if acc >= (total * 3) / 4 { // 75% of total length
-                        n75 = l;
-                        break;
-                    }
-                }
-                n75
-            }
-        } else {
-            0
-        };
-        
         // Calculate yield_gb and yield_gb_greater_than_25kb
         let total_bases = read_stats.num_bases;
         let yield_gb = total_bases as f64 / 1_000_000_000.0;
@@ -116,13 +98,13 @@ impl CraminoOutput {
         };
         
         result.num_alignments = read_stats.num_reads;
-        result.percent_from_total = 100.0; // Assume all alignments are counted
+        result.percent_from_total = percent_kept(read_stats.num_reads, &read_stats.filtered);
         result.num_reads = read_stats.num_reads;
         result.yield_gb = yield_gb;
         result.mean_coverage = mean_coverage;
         result.yield_gb_greater_than_25kb = yield_gb_gt_25kb;
         result.n50 = read_stats.n50;
-        result.n75 = n75;
+        result.n75 = read_stats.n75;
         result.median_length = read_stats.median_len;
         result.mean_length = read_stats.mean_len;
         
@@ -144,6 +126,9 @@ impl CraminoOutput {
         writeln!(&mut output, "N75\t{}", self.n75).unwrap();
         writeln!(&mut output, "Median length\t{:.2}", self.median_length).unwrap();
         writeln!(&mut output, "Mean length\t{:.2}", self.mean_length).unwrap();
+        for (label, count) in &self.filtered {
+            writeln!(&mut output, "{}\t{}", label, count).unwrap();
+        }
         writeln!(&mut output, "").unwrap(); // Empty line
         writeln!(&mut output, "Path\t{}", self.path.display()).unwrap();
         writeln!(&mut output, "Creation time\t{}", self.creation_time).unwrap();
@@ -170,6 +155,7 @@ pub struct EnhancedReadStats {
     pub num_reads: u64,
     pub num_bases: u64,
     pub lengths: Option<Vec<u32>>, // Store lengths for additional calculations
+    pub filtered: FilterCounts,
 }
 
 impl From<&ReadStats> for EnhancedReadStats {
@@ -179,7 +165,7 @@ impl From<&ReadStats> for EnhancedReadStats {
         // need to be populated separately
         Self {
             n50: stats.n50,
-            n75: 0, // Will be calculated later
+            n75: stats.n75,
             mean_len: stats.mean_len,
             median_len: stats.median_len,
             _mean_qual: stats.mean_qual,
@@ -187,32 +173,49 @@ impl From<&ReadStats> for EnhancedReadStats {
             num_reads: 0, // Will be calculated later
             num_bases: 0, // Will be calculated later
             lengths: None, // Will be populated later
+            filtered: stats.filtered,
         }
     }
 }
 
-/// Extract enhanced read statistics from a BAM file
-pub fn extract_enhanced_read_stats(bam_path: &Path) -> Result<EnhancedReadStats, Box<dyn std::error::Error>> {
-    let mut reader = bam::io::Reader::new(BufReader::new(File::open(bam_path)?));
-    let _header = reader.read_header()?;
+/// Share of alignments kept by the read-level filters, in percent
+fn percent_kept(num_reads: u64, filtered: &FilterCounts) -> f64 {
+    let total = num_reads + filtered.total();
+    if total == 0 {
+        100.0
+    } else {
+        num_reads as f64 / total as f64 * 100.0
+    }
+}
+
+/// Extract enhanced read statistics from one or more BAM/CRAM files, for the records
+/// passing the read-level filters
+pub fn extract_enhanced_read_stats(inputs: &[AlignmentInput], filter: &ReadFilter) -> Result<EnhancedReadStats, Box<dyn std::error::Error>> {
     let mut lengths = Vec::new();
     let mut quals = Vec::new();
     let mut num_reads: u64 = 0;
     let mut num_bases: u64 = 0;
+    let mut filtered = FilterCounts::default();
 
-    for result in reader.records() {
-        let record = result?;
-        let len = record.sequence().len() as u32;
-        lengths.push(len);
-        num_reads += 1;
-        num_bases += len as u64;
+    for input in inputs {
+        let mut reader = input.open_sequential()?;
+        let header = reader.read_header()?;
+
+        for result in reader.records(&header) {
+            let record = result?;
+            if let Some(reason) = filter.check_read(record.as_ref())? {
+                filtered.add(reason);
+                continue;
+            }
+            let len = record.sequence().len() as u32;
+            lengths.push(len);
+            num_reads += 1;
+            num_bases += len as u64;
 
-        // Collect mean quality per read (if available)
-        let qual = record.quality_scores();
-        let qual_slice = qual.as_ref();
-        if !qual_slice.is_empty() {
-            let q: f64 = qual_slice.iter().map(|&q| q as u32).sum::<u32>() as f64 / qual_slice.len() as f64;
-            quals.push(q);
+            // Collect mean quality per read (if available)
+            if let Some(q) = mean_base_quality(record.as_ref())? {
+                quals.push(q);
+            }
         }
     }
 
@@ -278,33 +281,41 @@ pub fn extract_enhanced_read_stats(bam_path: &Path) -> Result<EnhancedReadStats,
         num_reads,
         num_bases,
         lengths: Some(lengths), // Store lengths for additional calculations
+        filtered,
     })
 }
 
-/// Generate cramino-like output for a BAM file
+/// Generate cramino-like output for one or more BAM/CRAM files
+///
+/// With several inputs, the file name lists all of them and the path and creation time refer to the first.
 pub fn generate_cramino_output(
-    bam_path: &Path, 
+    inputs: &[AlignmentInput], 
     output_path: &Path, 
     read_stats: Option<&ReadStats>, 
+    filter: &ReadFilter,
     total_coverage: f64,
     genome_size: u64,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let cramino_output = if let Some(stats) = read_stats {
+    let bam_path = inputs[0].path.as_path();
+    let mut cramino_output = if let Some(stats) = read_stats {
         // Use provided stats if available
         let _enhanced_stats = EnhancedReadStats::from(stats);
-        CraminoOutput::from_read_stats(bam_path.to_path_buf(), stats, total_coverage, genome_size)
+        let mut output = CraminoOutput::from_read_stats(bam_path.to_path_buf(), stats, total_coverage, genome_size);
+        output.filtered = filter.removed_counts(&stats.filtered);
+        output
     } else {
         // Otherwise extract stats from BAM
-        let enhanced_stats = extract_enhanced_read_stats(bam_path)?;
+        let enhanced_stats = extract_enhanced_read_stats(inputs, filter)?;
         let path_buf = bam_path.to_path_buf();
+        let filtered = filter.removed_counts(&enhanced_stats.filtered);
         if enhanced_stats.num_reads == 0 {
-            // Empty BAM file
-            CraminoOutput::new_empty(path_buf)
+            // Empty BAM file (or every record filtered)
+            CraminoOutput { filtered, ..CraminoOutput::new_empty(path_buf) }
         } else {
             CraminoOutput {
                 file_name: path_buf.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default(),
                 num_alignments: enhanced_stats.num_reads,
-                percent_from_total: 100.0,
+                percent_from_total: percent_kept(enhanced_stats.num_reads, &enhanced_stats.filtered),
                 num_reads: enhanced_stats.num_reads,
                 yield_gb: enhanced_stats.num_bases as f64 / 1_000_000_000.0,
                 mean_coverage: if genome_size > 0 { enhanced_stats.num_bases as f64 / genome_size as f64 } else { total_coverage },
@@ -337,10 +348,19 @@ pub fn generate_cramino_output(
                     }
                     Err(_) => String::from("01/01/2000 00:00:00"),
                 },
+                filtered,
             }
         }
     };
 
+    if inputs.len() > 1 {
+        cramino_output.file_name = inputs
+            .iter()
+            .map(|input| input.path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join(",");
+    }
+
     cramino_output.write_to_file(output_path)?;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,471 @@
+// src/io/expr.rs
+// `--filter` expressions: a small language over record fields and aux tags, compiled once
+// and evaluated per record
+//
+// Grammar (loosest binding first):
+//   expr    := and ( "||" and )*
+//   and     := unary ( "&&" unary )*
+//   unary   := "!" unary | compare
+//   compare := bitand ( ( "==" | "!=" | "<" | "<=" | ">" | ">=" ) bitand )?
+//   bitand  := primary ( "&" primary )*
+//   primary := number | "string" | field | flag name | tag | [tag] | "(" expr ")"
+
+use super::filter::{alignment_identity, CigarTotals, FLAG_NAMES};
+use noodles_sam as sam;
+use std::cmp::Ordering;
+
+/// Record fields available in expressions
+const FIELDS: [&str; 9] = ["flag", "mapq", "length", "aligned_length", "identity", "pos", "end", "strand", "name"];
+
+/// A compiled `--filter` expression
+#[derive(Debug, Clone)]
+pub struct FilterExpr {
+    source: String,
+    root: Node,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Literal(Value),
+    Field(&'static str),
+    Tag([u8; 2]),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    BitAnd(Box<Node>, Box<Node>),
+    Compare(CompareOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Value of an expression; missing fields and tags never compare true
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Missing,
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl Value {
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Missing => false,
+            Value::Int(n) => *n != 0,
+            Value::Float(f) => *f != 0.0,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Int(b as i64)
+    }
+}
+
+/// Parse and compile a `--filter` expression, reporting errors with their column
+pub fn parse_expression(s: &str) -> Result<FilterExpr, String> {
+    let tokens = tokenize(s).map_err(|(column, message)| parse_error(s, column, &message))?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let root = parser
+        .expr()
+        .and_then(|root| match parser.peek() {
+            (Token::End, _) => Ok(root),
+            (token, column) => Err((*column, format!("unexpected {}", token.describe()))),
+        })
+        .map_err(|(column, message)| parse_error(s, column, &message))?;
+
+    Ok(FilterExpr { source: s.to_string(), root })
+}
+
+/// Error message pointing at a (1-based) column of the expression
+fn parse_error(s: &str, column: usize, message: &str) -> String {
+    format!("column {}: {}\n  {}\n  {}^", column, message, s, " ".repeat(column - 1))
+}
+
+impl FilterExpr {
+    /// The expression as given on the command line
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether a record matches the expression
+    pub fn matches(&self, record: &dyn sam::alignment::Record) -> std::io::Result<bool> {
+        Ok(eval(&self.root, record)?.is_true())
+    }
+}
+
+fn eval(node: &Node, record: &dyn sam::alignment::Record) -> std::io::Result<Value> {
+    Ok(match node {
+        Node::Literal(value) => value.clone(),
+        Node::Field(field) => field_value(field, record)?,
+        Node::Tag(tag) => tag_value(tag, record)?,
+        Node::Not(inner) => Value::from(!eval(inner, record)?.is_true()),
+        Node::And(a, b) => Value::from(eval(a, record)?.is_true() && eval(b, record)?.is_true()),
+        Node::Or(a, b) => Value::from(eval(a, record)?.is_true() || eval(b, record)?.is_true()),
+        Node::BitAnd(a, b) => match (eval(a, record)?, eval(b, record)?) {
+            (Value::Int(a), Value::Int(b)) => Value::Int(a & b),
+            _ => Value::Missing,
+        },
+        Node::Compare(op, a, b) => {
+            let ordering = eval(a, record)?.compare(&eval(b, record)?);
+            Value::from(match ordering {
+                None => false,
+                Some(ordering) => match op {
+                    CompareOp::Eq => ordering == Ordering::Equal,
+                    CompareOp::Ne => ordering != Ordering::Equal,
+                    CompareOp::Lt => ordering == Ordering::Less,
+                    CompareOp::Le => ordering != Ordering::Greater,
+                    CompareOp::Gt => ordering == Ordering::Greater,
+                    CompareOp::Ge => ordering != Ordering::Less,
+                },
+            })
+        }
+    })
+}
+
+fn field_value(field: &str, record: &dyn sam::alignment::Record) -> std::io::Result<Value> {
+    Ok(match field {
+        "flag" => Value::Int(u16::from(record.flags()?) as i64),
+        "mapq" => match record.mapping_quality().transpose()? {
+            Some(mapq) => Value::Int(u8::from(mapq) as i64),
+            None => Value::Missing,
+        },
+        "length" => {
+            let len = match record.sequence().len() as u64 {
+                0 => CigarTotals::from_record(record)?.query,
+                len => len,
+            };
+            Value::Int(len as i64)
+        }
+        "aligned_length" => Value::Int(CigarTotals::from_record(record)?.aligned as i64),
+        "identity" => {
+            let totals = CigarTotals::from_record(record)?;
+            alignment_identity(record, &totals)?.map_or(Value::Missing, Value::Float)
+        }
+        "pos" => match record.alignment_start().transpose()? {
+            Some(start) => Value::Int(start.get() as i64),
+            None => Value::Missing,
+        },
+        "end" => match record.alignment_end().transpose()? {
+            Some(end) => Value::Int(end.get() as i64),
+            None => Value::Missing,
+        },
+        "strand" => {
+            let strand = if record.flags()?.is_reverse_complemented() { "-" } else { "+" };
+            Value::Str(strand.to_string())
+        }
+        "name" => record.name().map_or(Value::Missing, |name| Value::Str(name.to_string())),
+        _ => Value::Missing,
+    })
+}
+
+fn tag_value(tag: &[u8; 2], record: &dyn sam::alignment::Record) -> std::io::Result<Value> {
+    use sam::alignment::record::data::field::{Tag, Value as TagValue};
+
+    Ok(match record.data().get(&Tag::new(tag[0], tag[1])).transpose()? {
+        None | Some(TagValue::Array(_)) => Value::Missing,
+        Some(TagValue::Character(c)) => Value::Str((c as char).to_string()),
+        Some(TagValue::String(s) | TagValue::Hex(s)) => Value::Str(s.to_string()),
+        Some(TagValue::Float(f)) => Value::Float(f as f64),
+        Some(value) => value.as_int().map_or(Value::Missing, Value::Int),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Value),
+    Str(String),
+    Ident(String),
+    Tag([u8; 2]),
+    Op(&'static str),
+    LParen,
+    RParen,
+    End,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Number(_) => "number".to_string(),
+            Token::Str(s) => format!("string \"{}\"", s),
+            Token::Ident(name) => format!("'{}'", name),
+            Token::Tag(tag) => format!("tag [{}]", String::from_utf8_lossy(tag)),
+            Token::Op(op) => format!("'{}'", op),
+            Token::LParen => "'('".to_string(),
+            Token::RParen => "')'".to_string(),
+            Token::End => "end of expression".to_string(),
+        }
+    }
+}
+
+/// Operators, longest first so that `<=` wins over `<`
+const OPERATORS: [&str; 11] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "&", "="];
+
+/// Split an expression into tokens with their (1-based) columns
+fn tokenize(s: &str) -> Result<Vec<(Token, usize)>, (usize, String)> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' || c == ')' {
+            tokens.push((if c == '(' { Token::LParen } else { Token::RParen }, column));
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let end = chars[i + 1..]
+                .iter()
+                .position(|&d| d == c)
+                .ok_or((column, "unterminated string".to_string()))?;
+            tokens.push((Token::Str(chars[i + 1..i + 1 + end].iter().collect()), column));
+            i += end + 2;
+        } else if c == '[' {
+            let tag: String = chars[i + 1..].iter().take_while(|&&d| d != ']').collect();
+            if i + 1 + tag.chars().count() >= chars.len() {
+                return Err((column, "unterminated tag, expected ']'".to_string()));
+            }
+            tokens.push((Token::Tag(parse_tag_name(&tag).ok_or((column + 1, format!("invalid tag '{}'", tag)))?), column));
+            i += tag.chars().count() + 2;
+        } else if c.is_ascii_digit() || c == '.' || (c == '-' && chars.get(i + 1).is_some_and(char::is_ascii_digit)) {
+            let text: String = std::iter::once(c)
+                .chain(chars[i + 1..].iter().copied().take_while(|d| d.is_ascii_alphanumeric() || *d == '.'))
+                .collect();
+            let number = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                i64::from_str_radix(hex, 16).ok().map(Value::Int)
+            } else if let Ok(n) = text.parse::<i64>() {
+                Some(Value::Int(n))
+            } else {
+                text.parse::<f64>().ok().map(Value::Float)
+            };
+            tokens.push((Token::Number(number.ok_or((column, format!("invalid number '{}'", text)))?), column));
+            i += text.len();
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let name: String = chars[i..].iter().take_while(|d| d.is_ascii_alphanumeric() || **d == '_').collect();
+            i += name.len();
+            tokens.push((Token::Ident(name), column));
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or((column, format!("unexpected character '{}'", c)))?;
+            // A single '=' is accepted as '=='
+            tokens.push((Token::Op(if *op == "=" { "==" } else { op }), column));
+            i += op.len();
+        }
+    }
+
+    tokens.push((Token::End, chars.len() + 1));
+    Ok(tokens)
+}
+
+/// A two-character SAM tag name such as `HP` or `qs`
+fn parse_tag_name(name: &str) -> Option<[u8; 2]> {
+    match name.as_bytes() {
+        &[a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphanumeric() => Some([a, b]),
+        _ => None,
+    }
+}
+
+type ParseResult = Result<Node, (usize, String)>;
+
+/// Recursive-descent parser over the token list
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &(Token, usize) {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> (Token, usize) {
+        let token = self.tokens[self.pos].clone();
+        if token.0 != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat_op(&mut self, op: &'static str) -> bool {
+        if self.peek().0 == Token::Op(op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> ParseResult {
+        let mut node = self.and()?;
+        while self.eat_op("||") {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> ParseResult {
+        let mut node = self.unary()?;
+        while self.eat_op("&&") {
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> ParseResult {
+        if self.eat_op("!") {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> ParseResult {
+        let left = self.bitand()?;
+        let op = match self.peek().0 {
+            Token::Op("==") => CompareOp::Eq,
+            Token::Op("!=") => CompareOp::Ne,
+            Token::Op("<") => CompareOp::Lt,
+            Token::Op("<=") => CompareOp::Le,
+            Token::Op(">") => CompareOp::Gt,
+            Token::Op(">=") => CompareOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.bitand()?;
+        Ok(Node::Compare(op, Box::new(left), Box::new(right)))
+    }
+
+    fn bitand(&mut self) -> ParseResult {
+        let mut node = self.primary()?;
+        while self.eat_op("&") {
+            node = Node::BitAnd(Box::new(node), Box::new(self.primary()?));
+        }
+        Ok(node)
+    }
+
+    fn primary(&mut self) -> ParseResult {
+        let (token, column) = self.next();
+        match token {
+            Token::Number(value) => Ok(Node::Literal(value)),
+            Token::Str(s) => Ok(Node::Literal(Value::Str(s))),
+            Token::Tag(tag) => Ok(Node::Tag(tag)),
+            Token::Ident(name) => identifier(&name).ok_or_else(|| {
+                (
+                    column,
+                    format!(
+                        "unknown field '{}' (expected one of {}, a flag name such as REVERSE, or a two-character tag)",
+                        name,
+                        FIELDS.join(", ")
+                    ),
+                )
+            }),
+            Token::LParen => {
+                let node = self.expr()?;
+                match self.next() {
+                    (Token::RParen, _) => Ok(node),
+                    (token, column) => Err((column, format!("expected ')' but found {}", token.describe()))),
+                }
+            }
+            token => Err((column, format!("expected a value but found {}", token.describe()))),
+        }
+    }
+}
+
+/// Resolve a bare name: a record field, a flag name, or else a two-character tag
+fn identifier(name: &str) -> Option<Node> {
+    if let Some(field) = FIELDS.iter().find(|field| **field == name) {
+        return Some(Node::Field(field));
+    }
+    if let Some((_, bit)) = FLAG_NAMES.iter().find(|(flag_name, _)| *flag_name == name) {
+        return Some(Node::Literal(Value::Int(*bit as i64)));
+    }
+    parse_tag_name(name).map(Node::Tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sam::alignment::record::cigar::{op::Kind, Op};
+    use sam::alignment::record::data::field::Tag;
+    use sam::alignment::record::{Flags, MappingQuality};
+    use sam::alignment::record_buf::data::field::Value as TagValue;
+    use sam::alignment::record_buf::{Cigar, Sequence};
+    use sam::alignment::RecordBuf;
+
+    fn record() -> RecordBuf {
+        let mut record = RecordBuf::builder()
+            .set_flags(Flags::REVERSE_COMPLEMENTED | Flags::SUPPLEMENTARY)
+            .set_mapping_quality(MappingQuality::new(30).unwrap())
+            .set_alignment_start(noodles_core::Position::new(100).unwrap())
+            .set_cigar(Cigar::from(vec![Op::new(Kind::SoftClip, 5), Op::new(Kind::Match, 20)]))
+            .set_sequence(Sequence::from(vec![b'A'; 25]))
+            .build();
+        record.data_mut().insert(Tag::new(b'q', b's'), TagValue::Float(12.5));
+        record.data_mut().insert(Tag::new(b'H', b'P'), TagValue::UInt8(1));
+        record.data_mut().insert(Tag::READ_GROUP, TagValue::from("bc01"));
+        record
+    }
+
+    fn matches(s: &str) -> bool {
+        parse_expression(s).unwrap().matches(&record()).unwrap()
+    }
+
+    #[test]
+    fn test_fields_and_tags() {
+        assert!(matches("qs > 10 && HP == 1"));
+        assert!(matches("[qs] >= 12.5 && RG == \"bc01\""));
+        assert!(matches("mapq >= 30 && length == 25 && aligned_length < 21"));
+        assert!(matches("pos == 100 && end == 119 && strand == '-'"));
+        assert!(matches("flag & REVERSE && (flag & 0x800) != 0"));
+        assert!(!matches("!(flag & SUPPLEMENTARY)"));
+        assert!(matches("mapq < 10 || HP = 1"));
+    }
+
+    #[test]
+    fn test_missing_tags_never_compare_true() {
+        assert!(!matches("XX == 1"));
+        assert!(!matches("XX != 1"));
+        assert!(matches("!XX"));
+        assert!(!matches("identity < 100"));
+    }
+
+    #[test]
+    fn test_parse_errors_report_column() {
+        let error = parse_expression("mapq > && qs").unwrap_err();
+        assert!(error.starts_with("column 8: expected a value but found '&&'"), "{}", error);
+        assert!(parse_expression("readlen > 5").unwrap_err().starts_with("column 1: unknown field 'readlen'"));
+        assert!(parse_expression("(mapq > 5").unwrap_err().starts_with("column 10: expected ')'"));
+        assert!(parse_expression("RG == \"bc01").unwrap_err().starts_with("column 7: unterminated string"));
+        assert!(parse_expression("mapq > 5 5").unwrap_err().starts_with("column 10: unexpected number"));
+        assert!(parse_expression("mapq # 5").unwrap_err().starts_with("column 6: unexpected character '#'"));
+        // Columns count characters, not bytes: a closed tag at the end is not unterminated
+        assert!(parse_expression("mapq > [é]").unwrap_err().starts_with("column 9: invalid tag 'é'"));
+        assert!(parse_expression("[é").unwrap_err().starts_with("column 1: unterminated tag"));
+    }
+}
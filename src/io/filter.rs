@@ -0,0 +1,607 @@
+// src/io/filter.rs
+// Record filters applied by every coverage engine before an alignment is counted
+
+use super::expr::FilterExpr;
+use crate::cli::Cli;
+use noodles_sam as sam;
+
+/// SAM flag names accepted by `--include-flags`/`--exclude-flags`, as in `samtools flags`
+pub(super) const FLAG_NAMES: [(&str, u16); 12] = [
+    ("PAIRED", 0x1),
+    ("PROPER_PAIR", 0x2),
+    ("UNMAP", 0x4),
+    ("MUNMAP", 0x8),
+    ("REVERSE", 0x10),
+    ("MREVERSE", 0x20),
+    ("READ1", 0x40),
+    ("READ2", 0x80),
+    ("SECONDARY", 0x100),
+    ("QCFAIL", 0x200),
+    ("DUP", 0x400),
+    ("SUPPLEMENTARY", 0x800),
+];
+
+/// Parse a flag mask given as a number (decimal or 0x hex) or comma-separated flag names
+pub fn parse_flags(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return u16::from_str_radix(hex, 16).map_err(|e| format!("invalid flag mask '{}': {}", s, e));
+    }
+    if let Ok(flags) = s.parse::<u16>() {
+        return Ok(flags);
+    }
+
+    let mut flags = 0;
+    for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let (_, bit) = FLAG_NAMES
+            .iter()
+            .find(|(flag_name, _)| flag_name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown flag '{}' (expected a number or names such as UNMAP,SECONDARY)", name))?;
+        flags |= bit;
+    }
+    Ok(flags)
+}
+
+/// Format a flag mask as hex followed by its flag names, e.g. `0x704 (UNMAP,SECONDARY,QCFAIL,DUP)`
+pub fn format_flags(flags: u16) -> String {
+    let names: Vec<&str> = FLAG_NAMES
+        .iter()
+        .filter(|&&(_, bit)| flags & bit != 0)
+        .map(|&(name, _)| name)
+        .collect();
+    if names.is_empty() {
+        format!("{:#x}", flags)
+    } else {
+        format!("{:#x} ({})", flags, names.join(","))
+    }
+}
+
+/// Parse a percentage between 0 and 100
+pub fn parse_percent(s: &str) -> Result<f64, String> {
+    let value: f64 = s.trim().parse().map_err(|e| format!("invalid percentage '{}': {}", s, e))?;
+    if (0.0..=100.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("percentage {} is not between 0 and 100", value))
+    }
+}
+
+/// Parse a subsampling fraction, greater than 0 and at most 1
+pub fn parse_fraction(s: &str) -> Result<f64, String> {
+    let value: f64 = s.trim().parse().map_err(|e| format!("invalid fraction '{}': {}", s, e))?;
+    if value > 0.0 && value <= 1.0 {
+        Ok(value)
+    } else {
+        Err(format!("fraction {} is not in (0, 1]", value))
+    }
+}
+
+/// Deterministic 64-bit hash of a read name and seed (FNV-1a followed by a splitmix64 finalizer)
+///
+/// Stable across platforms and runs, so a seed always selects the same reads.
+fn name_hash(name: &[u8], seed: u64) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for &b in name {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Why a read-level filter removed a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterReason {
+    Subsample,
+    MappingQuality,
+    ReadLength,
+    AlignedLength,
+    Identity,
+    Expression,
+}
+
+/// Number of records removed by each read-level filter
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FilterCounts {
+    pub subsample: u64,
+    pub mapq: u64,
+    pub read_length: u64,
+    pub aligned_length: u64,
+    pub identity: u64,
+    pub expression: u64,
+}
+
+impl FilterCounts {
+    /// Count one removed record
+    pub fn add(&mut self, reason: FilterReason) {
+        match reason {
+            FilterReason::Subsample => self.subsample += 1,
+            FilterReason::MappingQuality => self.mapq += 1,
+            FilterReason::ReadLength => self.read_length += 1,
+            FilterReason::AlignedLength => self.aligned_length += 1,
+            FilterReason::Identity => self.identity += 1,
+            FilterReason::Expression => self.expression += 1,
+        }
+    }
+
+    /// Add the counts of another collector (e.g. from another chunk)
+    pub fn merge(&mut self, other: FilterCounts) {
+        self.subsample += other.subsample;
+        self.mapq += other.mapq;
+        self.read_length += other.read_length;
+        self.aligned_length += other.aligned_length;
+        self.identity += other.identity;
+        self.expression += other.expression;
+    }
+
+    /// Records removed by any filter
+    pub fn total(&self) -> u64 {
+        self.subsample + self.mapq + self.read_length + self.aligned_length + self.identity + self.expression
+    }
+}
+
+/// Per-operation totals of a CIGAR string
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct CigarTotals {
+    /// Read bases in the alignment: M, I, =, X
+    pub(super) aligned: u64,
+    /// All read bases stored in the record: M, I, S, =, X
+    pub(super) query: u64,
+    /// Alignment columns: M, I, D, =, X
+    columns: u64,
+    insertions: u64,
+}
+
+impl CigarTotals {
+    pub(super) fn from_record(record: &dyn sam::alignment::Record) -> std::io::Result<Self> {
+        use sam::alignment::record::cigar::op::Kind;
+
+        let mut totals = Self::default();
+        for result in record.cigar().iter() {
+            let op = result?;
+            let len = op.len() as u64;
+            match op.kind() {
+                Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch => {
+                    totals.aligned += len;
+                    totals.query += len;
+                    totals.columns += len;
+                }
+                Kind::Insertion => {
+                    totals.aligned += len;
+                    totals.query += len;
+                    totals.columns += len;
+                    totals.insertions += len;
+                }
+                Kind::Deletion => totals.columns += len,
+                Kind::SoftClip => totals.query += len,
+                Kind::Skip | Kind::HardClip | Kind::Pad => {}
+            }
+        }
+        Ok(totals)
+    }
+}
+
+/// Alignment identity in percent: 1 - edits / alignment columns
+///
+/// Edits come from the `NM` tag, else from the `cs` tag, else from `MD` plus the CIGAR
+/// insertions. Returns `None` when the record has none of these tags.
+pub(super) fn alignment_identity(record: &dyn sam::alignment::Record, totals: &CigarTotals) -> std::io::Result<Option<f64>> {
+    use sam::alignment::record::data::field::{Tag, Value};
+
+    if totals.columns == 0 {
+        return Ok(None);
+    }
+    let data = record.data();
+
+    let edits = if let Some(value) = data.get(&Tag::EDIT_DISTANCE).transpose()? {
+        value.as_int().map(|nm| nm.max(0) as u64)
+    } else if let Some(Value::String(cs)) = data.get(&Tag::new(b'c', b's')).transpose()? {
+        Some(cs_edits(cs))
+    } else if let Some(Value::String(md)) = data.get(&Tag::MISMATCHED_POSITIONS).transpose()? {
+        Some(md_edits(md) + totals.insertions)
+    } else {
+        None
+    };
+
+    Ok(edits.map(|edits| (1.0 - edits as f64 / totals.columns as f64) * 100.0))
+}
+
+/// Mismatched, inserted and deleted bases in a minimap2 `cs` string
+fn cs_edits(cs: &[u8]) -> u64 {
+    let mut edits = 0;
+    let mut i = 0;
+    while i < cs.len() {
+        let op = cs[i];
+        i += 1;
+        let start = i;
+        while i < cs.len() && !matches!(cs[i], b':' | b'*' | b'+' | b'-' | b'~' | b'=') {
+            i += 1;
+        }
+        match op {
+            // Substitution: *<ref><query>
+            b'*' => edits += 1,
+            b'+' | b'-' => edits += (i - start) as u64,
+            _ => {}
+        }
+    }
+    edits
+}
+
+/// Mismatched and deleted reference bases in an `MD` string
+fn md_edits(md: &[u8]) -> u64 {
+    md.iter().filter(|b| b.is_ascii_alphabetic()).count() as u64
+}
+
+/// Whether subsampling keeps a record
+///
+/// The decision only depends on the read name, so all alignments of a read (primary,
+/// secondary and supplementary) are kept or dropped together. Records without a name
+/// (QNAME `*`) are decided by their position, flags and bases instead, so that they are not
+/// all kept or all dropped together.
+fn subsample_keeps(record: &dyn sam::alignment::Record, fraction: f64, seed: u64) -> std::io::Result<bool> {
+    if fraction >= 1.0 {
+        return Ok(true);
+    }
+    let hash = match record.name() {
+        Some(name) => name_hash(name.as_ref(), seed),
+        None => {
+            let start = record.alignment_start().transpose()?.map_or(0, |start| start.get() as u64);
+            let mut key = start.to_le_bytes().to_vec();
+            key.extend(u16::from(record.flags()?).to_le_bytes());
+            key.extend(record.sequence().iter());
+            name_hash(&key, seed)
+        }
+    };
+    // Top 53 bits as a uniform value in [0, 1)
+    Ok(((hash >> 11) as f64 / (1u64 << 53) as f64) < fraction)
+}
+
+/// Decides which alignments count towards coverage
+///
+/// Flag masks only apply to coverage; read-level filters such as `--min-mapq` apply to
+/// coverage and read statistics alike.
+#[derive(Debug, Clone)]
+pub struct ReadFilter {
+    include_flags: u16,
+    exclude_flags: u16,
+    min_mapq: u8,
+    min_read_length: u32,
+    min_aligned_length: u32,
+    min_identity: f64,
+    expression: Option<FilterExpr>,
+    /// Fraction of reads to keep and the seed selecting them
+    subsample: Option<(f64, u64)>,
+}
+
+impl ReadFilter {
+    /// Build the filter from the command line options
+    pub fn from_cli(cli: &Cli) -> Self {
+        Self {
+            include_flags: cli.include_flags,
+            exclude_flags: cli.exclude_flags,
+            min_mapq: cli.min_mapq,
+            min_read_length: cli.min_read_length,
+            min_aligned_length: cli.min_aligned_length,
+            min_identity: cli.min_identity,
+            expression: cli.filter_expression.clone(),
+            subsample: cli.subsample.map(|fraction| (fraction, cli.seed)),
+        }
+    }
+
+    /// Why the read-level filters remove a record, if they do
+    ///
+    /// A missing MAPQ (255) passes, as in samtools. Aligned length and identity only apply
+    /// to mapped records, and records without NM, cs or MD tags pass the identity filter.
+    /// Subsampling comes first, and the `--filter` expression is checked last, on every record.
+    pub fn check_read(&self, record: &dyn sam::alignment::Record) -> std::io::Result<Option<FilterReason>> {
+        if let Some((fraction, seed)) = self.subsample
+            && !subsample_keeps(record, fraction, seed)?
+        {
+            return Ok(Some(FilterReason::Subsample));
+        }
+
+        let reason = self.check_thresholds(record)?;
+        if reason.is_none()
+            && let Some(expression) = &self.expression
+            && !expression.matches(record)?
+        {
+            return Ok(Some(FilterReason::Expression));
+        }
+        Ok(reason)
+    }
+
+    /// The threshold filters of `check_read`
+    ///
+    /// Unmapped records are only held to the read length: their MAPQ (0), aligned length and
+    /// identity say nothing about the read.
+    fn check_thresholds(&self, record: &dyn sam::alignment::Record) -> std::io::Result<Option<FilterReason>> {
+        let unmapped = record.flags()?.is_unmapped();
+
+        if self.min_mapq > 0 && !unmapped {
+            let mapq = record.mapping_quality().transpose()?.map_or(u8::MAX, u8::from);
+            if mapq < self.min_mapq {
+                return Ok(Some(FilterReason::MappingQuality));
+            }
+        }
+
+        if self.min_read_length == 0 && self.min_aligned_length == 0 && self.min_identity == 0.0 {
+            return Ok(None);
+        }
+        let totals = CigarTotals::from_record(record)?;
+
+        if self.min_read_length > 0 {
+            // Records stored without SEQ (e.g. secondary alignments) fall back to the CIGAR
+            let read_length = match record.sequence().len() as u64 {
+                0 => totals.query,
+                len => len,
+            };
+            if read_length < self.min_read_length as u64 {
+                return Ok(Some(FilterReason::ReadLength));
+            }
+        }
+
+        if unmapped {
+            return Ok(None);
+        }
+
+        if self.min_aligned_length > 0 && totals.aligned < self.min_aligned_length as u64 {
+            return Ok(Some(FilterReason::AlignedLength));
+        }
+
+        if self.min_identity > 0.0
+            && let Some(identity) = alignment_identity(record, &totals)?
+            && identity < self.min_identity
+        {
+            return Ok(Some(FilterReason::Identity));
+        }
+
+        Ok(None)
+    }
+
+    /// Whether a record is counted: all include flags set, no exclude flag set, and
+    /// passing the read-level filters
+    ///
+    /// Unmapped records never count, whatever the masks.
+    pub fn passes(&self, record: &dyn sam::alignment::Record) -> std::io::Result<bool> {
+        Ok(self.passes_flags(record)? && self.check_read(record)?.is_none())
+    }
+
+    /// The flag part of `passes`, for records whose read-level filters were already checked
+    /// (see [`ReadStatsCollector::add_record_with_filter`])
+    ///
+    /// [`ReadStatsCollector::add_record_with_filter`]: crate::utils::ReadStatsCollector::add_record_with_filter
+    pub fn passes_flags(&self, record: &dyn sam::alignment::Record) -> std::io::Result<bool> {
+        let flags = record.flags()?;
+        if flags.is_unmapped() {
+            return Ok(false);
+        }
+        let bits = u16::from(flags);
+        Ok(bits & self.include_flags == self.include_flags && bits & self.exclude_flags == 0)
+    }
+
+    /// Label and count of the records removed by each active read-level filter
+    pub fn removed_counts(&self, counts: &FilterCounts) -> Vec<(String, u64)> {
+        let mut removed = Vec::new();
+        if let Some((fraction, seed)) = self.subsample {
+            removed.push((format!("Alignments dropped by subsampling ({}, seed {})", fraction, seed), counts.subsample));
+        }
+        if self.min_mapq > 0 {
+            removed.push((format!("Alignments with MAPQ < {}", self.min_mapq), counts.mapq));
+        }
+        if self.min_read_length > 0 {
+            removed.push((format!("Alignments with read length < {}", self.min_read_length), counts.read_length));
+        }
+        if self.min_aligned_length > 0 {
+            removed.push((format!("Alignments with aligned length < {}", self.min_aligned_length), counts.aligned_length));
+        }
+        if self.min_identity > 0.0 {
+            removed.push((format!("Alignments with identity < {}%", self.min_identity), counts.identity));
+        }
+        if let Some(expression) = &self.expression {
+            removed.push((format!("Alignments not matching '{}'", expression.source()), counts.expression));
+        }
+        removed
+    }
+
+    /// `##` lines describing the filter, written at the top of coverage outputs
+    pub fn header_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("##include_flags={}", format_flags(self.include_flags)),
+            format!("##exclude_flags={}", format_flags(self.exclude_flags)),
+            format!("##min_mapq={}", self.min_mapq),
+            format!("##min_read_length={}", self.min_read_length),
+            format!("##min_aligned_length={}", self.min_aligned_length),
+            format!("##min_identity={}", self.min_identity),
+        ];
+        if let Some(expression) = &self.expression {
+            lines.push(format!("##filter={}", expression.source()));
+        }
+        if let Some((fraction, seed)) = self.subsample {
+            lines.push(format!("##subsample={} seed={}", fraction, seed));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sam::alignment::record::cigar::{op::Kind, Op};
+    use sam::alignment::record::data::field::Tag;
+    use sam::alignment::record::Flags;
+    use sam::alignment::record_buf::data::field::Value;
+    use sam::alignment::record_buf::{Cigar, Sequence};
+    use sam::alignment::RecordBuf;
+
+    fn record(flags: Flags) -> RecordBuf {
+        RecordBuf::builder().set_flags(flags).build()
+    }
+
+    fn masks(include_flags: u16, exclude_flags: u16) -> ReadFilter {
+        ReadFilter {
+            include_flags,
+            exclude_flags,
+            min_mapq: 0,
+            min_read_length: 0,
+            min_aligned_length: 0,
+            min_identity: 0.0,
+            expression: None,
+            subsample: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        assert_eq!(parse_flags("1796"), Ok(0x704));
+        assert_eq!(parse_flags("0x704"), Ok(0x704));
+        assert_eq!(parse_flags("UNMAP,SECONDARY,QCFAIL,DUP"), Ok(0x704));
+        assert_eq!(parse_flags("supplementary"), Ok(0x800));
+        assert!(parse_flags("SECONDRY").is_err());
+        assert_eq!(format_flags(0x704), "0x704 (UNMAP,SECONDARY,QCFAIL,DUP)");
+        assert_eq!(format_flags(0), "0x0");
+    }
+
+    #[test]
+    fn test_include_and_exclude_masks() {
+        let filter = masks(0, 0x704);
+        assert!(filter.passes(&record(Flags::empty())).unwrap());
+        assert!(filter.passes(&record(Flags::SUPPLEMENTARY)).unwrap());
+        assert!(!filter.passes(&record(Flags::SECONDARY)).unwrap());
+        assert!(!filter.passes(&record(Flags::DUPLICATE | Flags::REVERSE_COMPLEMENTED)).unwrap());
+
+        let filter = masks(0x10, 0);
+        assert!(filter.passes(&record(Flags::REVERSE_COMPLEMENTED)).unwrap());
+        assert!(!filter.passes(&record(Flags::empty())).unwrap());
+        // Unmapped records are never counted
+        assert!(!filter.passes(&record(Flags::UNMAPPED | Flags::REVERSE_COMPLEMENTED)).unwrap());
+    }
+
+    #[test]
+    fn test_min_mapq() {
+        use sam::alignment::record::MappingQuality;
+
+        let filter = ReadFilter { min_mapq: 20, ..masks(0, 0x704) };
+        let with_mapq = |mapq| RecordBuf::builder()
+            .set_flags(Flags::empty())
+            .set_mapping_quality(MappingQuality::new(mapq).unwrap())
+            .build();
+
+        assert_eq!(filter.check_read(&with_mapq(0)).unwrap(), Some(FilterReason::MappingQuality));
+        assert!(!filter.passes(&with_mapq(19)).unwrap());
+        assert!(filter.passes(&with_mapq(20)).unwrap());
+        // Missing MAPQ (255) is kept
+        assert!(filter.passes(&record(Flags::empty())).unwrap());
+        // Unmapped reads (MAPQ 0) stay in the read stats and are not counted as removed
+        let unmapped = RecordBuf::builder()
+            .set_flags(Flags::UNMAPPED)
+            .set_mapping_quality(MappingQuality::new(0).unwrap())
+            .build();
+        assert_eq!(filter.check_read(&unmapped).unwrap(), None);
+        assert_eq!(filter.removed_counts(&FilterCounts { mapq: 3, ..Default::default() }), vec![("Alignments with MAPQ < 20".to_string(), 3)]);
+    }
+
+    fn aligned(ops: &[(Kind, usize)], seq_len: usize, tag: Option<(&[u8; 2], Value)>) -> RecordBuf {
+        let mut record = RecordBuf::builder()
+            .set_flags(Flags::empty())
+            .set_alignment_start(noodles_core::Position::MIN)
+            .set_cigar(Cigar::from(ops.iter().map(|&(kind, len)| Op::new(kind, len)).collect::<Vec<_>>()))
+            .set_sequence(Sequence::from(vec![b'A'; seq_len]))
+            .build();
+        if let Some((tag, value)) = tag {
+            record.data_mut().insert(Tag::new(tag[0], tag[1]), value);
+        }
+        record
+    }
+
+    #[test]
+    fn test_length_and_identity_filters() {
+        // 10S 90M 5I 5D: 95 aligned read bases, 105 read bases, 100 alignment columns
+        let ops = [(Kind::SoftClip, 10), (Kind::Match, 90), (Kind::Insertion, 5), (Kind::Deletion, 5)];
+        let filter = ReadFilter { min_read_length: 100, min_aligned_length: 95, min_identity: 85.0, ..masks(0, 0) };
+
+        assert_eq!(filter.check_read(&aligned(&ops, 105, None)).unwrap(), None);
+        assert_eq!(filter.check_read(&aligned(&ops, 99, None)).unwrap(), Some(FilterReason::ReadLength));
+        // Without SEQ the read length comes from the CIGAR
+        assert_eq!(filter.check_read(&aligned(&ops, 0, None)).unwrap(), None);
+
+        let short = [(Kind::Match, 94), (Kind::Deletion, 6)];
+        assert_eq!(filter.check_read(&aligned(&short, 100, None)).unwrap(), Some(FilterReason::AlignedLength));
+
+        // NM 20 over 100 columns is 80% identity
+        let nm = aligned(&ops, 105, Some((b"NM", Value::UInt8(20))));
+        assert_eq!(filter.check_read(&nm).unwrap(), Some(FilterReason::Identity));
+        let nm = aligned(&ops, 105, Some((b"NM", Value::UInt8(15))));
+        assert_eq!(filter.check_read(&nm).unwrap(), None);
+    }
+
+    #[test]
+    fn test_filter_expression() {
+        let expression = crate::io::expr::parse_expression("qs >= 10").unwrap();
+        let filter = ReadFilter { expression: Some(expression), ..masks(0, 0x704) };
+        let ops = [(Kind::Match, 10)];
+
+        assert!(filter.passes(&aligned(&ops, 10, Some((b"qs", Value::Float(12.0))))).unwrap());
+        assert_eq!(filter.check_read(&aligned(&ops, 10, Some((b"qs", Value::Float(8.5))))).unwrap(), Some(FilterReason::Expression));
+        // Records without the tag do not match
+        assert_eq!(filter.check_read(&aligned(&ops, 10, None)).unwrap(), Some(FilterReason::Expression));
+        // Engines gathering read stats evaluate the expression once and then only check the flags
+        let mut stats = crate::utils::ReadStatsCollector::default();
+        assert!(!stats.add_record_with_filter(&aligned(&ops, 10, None), &filter).unwrap());
+        assert!(filter.passes_flags(&aligned(&ops, 10, None)).unwrap());
+        assert!(stats.add_record_with_filter(&aligned(&ops, 10, Some((b"qs", Value::Float(12.0)))), &filter).unwrap());
+        assert_eq!(filter.removed_counts(&FilterCounts { expression: 2, ..Default::default() }), vec![("Alignments not matching 'qs >= 10'".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_subsample_by_read_name() {
+        let named = |name: &str, flags| RecordBuf::builder().set_name(name).set_flags(flags).build();
+        let filter = ReadFilter { subsample: Some((0.25, 7)), ..masks(0, 0x704) };
+
+        let kept = (0..10_000)
+            .filter(|i| filter.check_read(&named(&format!("read{}", i), Flags::empty())).unwrap().is_none())
+            .count();
+        assert!((2_300..2_700).contains(&kept), "kept {} of 10000", kept);
+
+        // Alignments of one read share the decision, and the seed changes the selection
+        for i in 0..100 {
+            let name = format!("read{}", i);
+            let primary = filter.check_read(&named(&name, Flags::empty())).unwrap();
+            assert_eq!(filter.check_read(&named(&name, Flags::SUPPLEMENTARY)).unwrap(), primary);
+        }
+        let other_seed = ReadFilter { subsample: Some((0.25, 8)), ..masks(0, 0x704) };
+        assert!((0..100).any(|i| {
+            let record = named(&format!("read{}", i), Flags::empty());
+            filter.check_read(&record).unwrap() != other_seed.check_read(&record).unwrap()
+        }));
+
+        // Unnamed records are told apart by position, flags and bases, always the same way
+        let unnamed = |start| {
+            RecordBuf::builder()
+                .set_flags(Flags::empty())
+                .set_alignment_start(noodles_core::Position::new(start).unwrap())
+                .build()
+        };
+        let kept = (1..=10_000).filter(|&i| filter.check_read(&unnamed(i)).unwrap().is_none()).count();
+        assert!((2_300..2_700).contains(&kept), "kept {} of 10000 unnamed", kept);
+        assert_eq!(filter.check_read(&unnamed(42)).unwrap(), filter.check_read(&unnamed(42)).unwrap());
+
+        assert!(parse_fraction("0").is_err());
+        assert_eq!(parse_fraction("0.375"), Ok(0.375));
+    }
+
+    #[test]
+    fn test_identity_from_cs_and_md() {
+        let ops = [(Kind::Match, 10), (Kind::Insertion, 2), (Kind::Match, 5), (Kind::Deletion, 3)];
+        let totals = CigarTotals { columns: 20, insertions: 2, ..Default::default() };
+
+        // 2 mismatches, 2 inserted and 3 deleted bases: 7 edits over 20 columns
+        let cs = aligned(&ops, 17, Some((b"cs", Value::from(":4*ag:2*ct:2+tt:5-aaa"))));
+        assert_eq!(alignment_identity(&cs, &totals).unwrap(), Some(65.0));
+        let md = aligned(&ops, 17, Some((b"MD", Value::from("4G2C7^AAA0"))));
+        assert_eq!(alignment_identity(&md, &totals).unwrap(), Some(65.0));
+        assert_eq!(alignment_identity(&aligned(&ops, 17, None), &totals).unwrap(), None);
+    }
+}
@@ -0,0 +1,259 @@
+// src/io/groups.rs
+// Per-group mode for nanocov: coverage and read stats split by a tag value (read group, barcode)
+
+use super::accumulator::CoverageEvents;
+use super::alignment::AlignmentInput;
+use super::blocks::{CoverageRules, MateOverlaps};
+use super::contigs::ContigNames;
+use super::cramino::CraminoOutput;
+use super::filter::ReadFilter;
+use super::{effective_genome_size, output_header_lines, report_coverage, CoverageMap, ReferenceOrder, ReferenceRegions};
+use crate::cli::Cli;
+use crate::utils::{ReadStats, ReadStatsCollector};
+use noodles_sam::alignment::record::data::field::{Tag, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Group name for records without the split tag
+const UNTAGGED: &str = "untagged";
+
+/// Coverage and read stats of one group
+#[derive(Default)]
+struct GroupAccumulator {
+    /// Events counted on the current reference sequence
+    events: CoverageEvents,
+    /// Coverage of the reference sequences already read
+    coverage: CoverageMap,
+    stats: ReadStatsCollector,
+}
+
+/// Parse a two-character SAM tag such as `RG` or `BC`
+pub fn parse_tag(s: &str) -> Result<Tag, String> {
+    match s.as_bytes() {
+        &[a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphanumeric() => Ok(Tag::new(a, b)),
+        _ => Err(format!("invalid tag '{}' (expected two characters such as RG or BC)", s)),
+    }
+}
+
+/// Compute coverage and read stats separately for each value of the `--split-by` tag
+///
+/// All inputs are read in one sequential pass. Each group gets its own coverage file
+/// (and plots) named after the group; the read stats of all groups go to one file
+/// (<output>.groups.txt), one block per group. Returns the read stats of all groups combined.
+pub fn run_coverage_by_group(
+    cli: &Cli,
+    inputs: &[AlignmentInput],
+    filter: &ReadFilter,
+    rules: &CoverageRules,
+) -> Result<(ReadStats, noodles_sam::Header), Box<dyn std::error::Error>> {
+    let tag = cli.split_by.ok_or("--split-by is required for per-group mode")?;
+    let tag_name = String::from_utf8_lossy(tag.as_ref()).to_string();
+    println!("Splitting coverage by the {} tag", tag_name);
+
+    let mut reader = inputs[0].open_sequential()?;
+    let header = reader.read_header()?;
+
+    let contigs = ContigNames::new(cli, &header)?;
+    let ref_regions = ReferenceRegions::new(cli, &header, &contigs)?;
+
+    let mut groups: BTreeMap<String, GroupAccumulator> = BTreeMap::new();
+    let mut mates = MateOverlaps::default();
+
+    for (i, input) in inputs.iter().enumerate() {
+        if i > 0 {
+            reader = input.open_sequential()?;
+            reader.read_header()?;
+        }
+
+        // Every group is complete on a reference sequence once the next one starts, so only
+        // the events of the current one are kept (see `run_coverage_sweep`)
+        let mut order = ReferenceOrder::default();
+        for result in reader.records(&header) {
+            let record = result?;
+
+            let ref_id = match record.reference_sequence_id(&header) {
+                Some(Ok(id)) => Some(id),
+                _ => None,
+            };
+            if let Some(ref_id) = ref_id
+                && let Some(done) = order.advance(ref_id, &ref_regions, input)?
+            {
+                for accumulator in groups.values_mut() {
+                    ref_regions.flush(&mut accumulator.events, done, &mut accumulator.coverage);
+                }
+            }
+
+            let group = match record.data().get(&tag).transpose()? {
+                Some(value) => tag_value_to_string(&value),
+                None => UNTAGGED.to_string(),
+            };
+            if !cli.groups.is_empty() && !cli.groups.contains(&group) {
+                continue;
+            }
+
+            let accumulator = groups.entry(group).or_default();
+            let passed = accumulator.stats.add_record_with_filter(record.as_ref(), filter)?;
+
+            let Some(ref_id) = ref_id else {
+                continue;
+            };
+            if !passed || !filter.passes_flags(record.as_ref())? {
+                continue;
+            }
+
+            let blocks = rules.counted_blocks(&header, record.as_ref(), &mut mates)?;
+            ref_regions.add_blocks(&mut accumulator.events, ref_id, &blocks);
+        }
+        if let Some(last) = order.current {
+            for accumulator in groups.values_mut() {
+                ref_regions.flush(&mut accumulator.events, last, &mut accumulator.coverage);
+            }
+        }
+    }
+
+    for group in &cli.groups {
+        if !groups.contains_key(group) {
+            eprintln!("Warning: no records found for group '{}'", group);
+        }
+    }
+
+    let mut combined_stats = ReadStatsCollector::default();
+    let mut group_stats: Vec<(String, ReadStats)> = Vec::with_capacity(groups.len());
+
+    let group_names: Vec<String> = groups.keys().cloned().collect();
+    let outputs = group_output_paths(&cli.output, &group_names);
+
+    for ((group, accumulator), output) in groups.into_iter().zip(outputs) {
+        println!("Group {}:", group);
+
+        combined_stats.merge(accumulator.stats.clone());
+        let read_stats = accumulator.stats.finish();
+
+        let mut header_lines = output_header_lines(filter, rules);
+        header_lines.push(format!("##group={}:{}", tag_name, group));
+        report_coverage(cli, &contigs, &accumulator.coverage, &output, &header_lines, Some(&group), Some(&read_stats))?;
+        println!("Wrote coverage for group {} to {:?}", group, output);
+
+        group_stats.push((group, read_stats));
+    }
+
+    let stats_path = group_stats_path(&cli.output);
+    write_group_stats(&stats_path, inputs, filter, effective_genome_size(cli, &header)?, &group_stats)?;
+    println!("Wrote per-group read stats to {:?}", stats_path);
+
+    Ok((combined_stats.finish(), header))
+}
+
+/// Text of a tag value used as group name
+fn tag_value_to_string(value: &Value) -> String {
+    match value {
+        Value::Character(c) => (*c as char).to_string(),
+        Value::String(s) | Value::Hex(s) => s.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Array(_) => "array".to_string(),
+        other => other.as_int().map(|n| n.to_string()).unwrap_or_default(),
+    }
+}
+
+/// Coverage outputs of the groups, in order: `coverage.tsv` becomes `coverage.<group>.tsv`
+///
+/// Characters outside `[A-Za-z0-9._-]` become `_`; when that makes two groups share a file
+/// name (`run 1` and `run_1`), the later one gets a numbered suffix (`run_1-2`).
+fn group_output_paths(output: &Path, groups: &[String]) -> Vec<PathBuf> {
+    let mut used: HashSet<String> = HashSet::new();
+    groups
+        .iter()
+        .map(|group| {
+            // Keep group names usable as file name parts
+            let sanitized: String = group
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+                .collect();
+            let mut name = sanitized.clone();
+            let mut n = 1;
+            while !used.insert(name.clone()) {
+                n += 1;
+                name = format!("{}-{}", sanitized, n);
+            }
+            if name != *group {
+                println!("Group '{}' is written as '{}'", group, name);
+            }
+            group_output_path(output, &name)
+        })
+        .collect()
+}
+
+/// Coverage output of one group with a file-name-safe name
+fn group_output_path(output: &Path, group: &str) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match output.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, group, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, group),
+    };
+    output.with_file_name(file_name)
+}
+
+/// Per-group read stats file next to the coverage output
+fn group_stats_path(output: &Path) -> PathBuf {
+    output.with_extension("groups.txt")
+}
+
+/// Write one cramino-style read stats block per group
+fn write_group_stats(
+    path: &Path,
+    inputs: &[AlignmentInput],
+    filter: &ReadFilter,
+    genome_size: u64,
+    group_stats: &[(String, ReadStats)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let file_names = inputs
+        .iter()
+        .map(|input| input.path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    for (i, (group, read_stats)) in group_stats.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        let mut block = CraminoOutput::from_read_stats(inputs[0].path.clone(), read_stats, 0.0, genome_size);
+        block.file_name = file_names.clone();
+        block.filtered = filter.removed_counts(&read_stats.filtered);
+        writeln!(out, "Group\t{}", group)?;
+        write!(out, "{}", block.format())?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tag() {
+        assert_eq!(parse_tag("RG"), Ok(Tag::READ_GROUP));
+        assert_eq!(parse_tag("BC"), Ok(Tag::SAMPLE_BARCODE_SEQUENCE));
+        assert!(parse_tag("R").is_err());
+        assert!(parse_tag("1G").is_err());
+    }
+
+    #[test]
+    fn test_group_output_path() {
+        assert_eq!(group_output_path(Path::new("out/coverage.tsv"), "bc01"), PathBuf::from("out/coverage.bc01.tsv"));
+        let groups = ["run 1/bc02".to_string()];
+        assert_eq!(group_output_paths(Path::new("coverage"), &groups), vec![PathBuf::from("coverage.run_1_bc02")]);
+
+        // Groups that only differ in replaced characters get distinct files
+        let groups = ["a/b", "a_b", "a b", "a_b-2"].map(str::to_string);
+        assert_eq!(
+            group_output_paths(Path::new("cov.tsv"), &groups),
+            ["cov.a_b.tsv", "cov.a_b-2.tsv", "cov.a_b-3.tsv", "cov.a_b-2-2.tsv"].map(PathBuf::from).to_vec()
+        );
+        assert_eq!(group_stats_path(Path::new("out/coverage.tsv")), PathBuf::from("out/coverage.groups.txt"));
+    }
+}
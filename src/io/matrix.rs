@@ -0,0 +1,391 @@
+// src/io/matrix.rs
+// Multi-sample mode for nanocov: one coverage column per input, plus a per-sample summary
+
+use super::alignment::AlignmentInput;
+use super::blocks::CoverageRules;
+use super::contigs::ContigNames;
+use super::filter::ReadFilter;
+use super::{
+    collect_unplaced_read_stats, create_chunk_jobs, load_bed, load_targets, output_header_lines, process_chunks_parallel,
+    read_stats_in_pass, regions_for_chromosome, CoverageChunk,
+};
+use crate::cli::Cli;
+use crate::utils::{collect_read_stats, ReadStats, ReadStatsCollector};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Coverage of one sample: chromosome -> position -> depth
+type SampleCoverage = HashMap<String, HashMap<u32, u32>>;
+
+/// Compute coverage for every input separately over a shared chunk plan and write a matrix
+///
+/// Rows are positions (or windows with `--window`), columns are samples. A per-sample
+/// summary is written next to the matrix. Returns the read stats of all samples combined.
+pub fn run_coverage_multi_sample(
+    cli: &Cli,
+    inputs: &[AlignmentInput],
+    filter: &ReadFilter,
+    rules: &CoverageRules,
+) -> Result<(ReadStats, noodles_sam::Header), Box<dyn std::error::Error>> {
+    println!("Using multi-sample mode with {} samples", inputs.len());
+
+    if let Some(input) = inputs.iter().find(|input| input.index_path().is_none()) {
+        return Err(format!("Multi-sample mode needs an index for every input; none found for {:?}", input.path).into());
+    }
+
+    // Set thread pool size
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(|e| format!("Failed to set thread pool: {}", e))?;
+    }
+
+    // Read each header and index once; all inputs share the same reference dictionary,
+    // so one chunk plan fits every sample
+    let indexed_inputs = inputs
+        .iter()
+        .map(AlignmentInput::load_indexed)
+        .collect::<Result<Vec<_>, _>>()?;
+    let header = &indexed_inputs[0].header;
+
+    let contigs = ContigNames::new(cli, header)?;
+    let bed_regions = load_targets(cli, &contigs)?;
+    let chrom_bed_regions = load_bed(cli.chrom_bed.as_ref(), &contigs)?;
+    let exclude_regions = load_bed(cli.exclude_bed.as_ref(), &contigs)?;
+
+    let collect_stats = read_stats_in_pass(cli, inputs);
+    let chunk_jobs =
+        create_chunk_jobs(header, &bed_regions, &chrom_bed_regions, &exclude_regions, cli.chunk_size, collect_stats)?;
+    let target_bases: u64 = chunk_jobs.iter().map(|chunk| (chunk.end - chunk.start) as u64).sum();
+
+    println!("Created {} chunk jobs for {} samples", chunk_jobs.len(), inputs.len());
+
+    // Process every (chunk, sample) pair in parallel
+    let chunk_results = process_chunks_parallel(&indexed_inputs, &chunk_jobs, filter, rules);
+
+    // Merge chunk results per sample
+    let mut sample_coverages: Vec<SampleCoverage> = vec![HashMap::new(); inputs.len()];
+    let mut sample_stats: Vec<ReadStatsCollector> = vec![ReadStatsCollector::default(); inputs.len()];
+    for result in chunk_results {
+        let (sample, chunk_coverage, chunk_stats) = result?;
+        for (chrom, positions) in chunk_coverage {
+            let chrom_coverage = sample_coverages[sample].entry(chrom).or_default();
+            for (pos, count) in positions {
+                *chrom_coverage.entry(pos).or_insert(0) += count;
+            }
+        }
+        sample_stats[sample].merge(chunk_stats);
+    }
+
+    // Read statistics per sample, from the coverage pass or one pass over each input
+    if collect_stats {
+        for (indexed, stats) in indexed_inputs.iter().zip(sample_stats.iter_mut()) {
+            collect_unplaced_read_stats(indexed, filter, stats)?;
+        }
+    } else {
+        sample_stats = inputs
+            .par_iter()
+            .map(|input| collect_read_stats(std::slice::from_ref(input), filter).map_err(|e| format!("{:?}: {}", input.path, e)))
+            .collect::<Result<_, _>>()?;
+    }
+
+    let names = sample_names(inputs);
+    let header_lines = output_header_lines(filter, rules);
+
+    match cli.window {
+        Some(window) if window > 1 => {
+            let chrom_lengths: HashMap<String, u32> = header
+                .reference_sequences()
+                .iter()
+                .map(|(name, ref_seq)| (name.to_string(), ref_seq.length().get() as u32))
+                .collect();
+            // Counted bases of each window come from the regions the chunks were made of
+            let chrom_regions: HashMap<String, Vec<(u32, u32)>> = chrom_lengths
+                .iter()
+                .map(|(chrom, &length)| {
+                    let regions =
+                        regions_for_chromosome(chrom, length, &bed_regions, &chrom_bed_regions, &exclude_regions);
+                    (chrom.clone(), regions)
+                })
+                .collect();
+            write_window_matrix(
+                &sample_coverages,
+                &names,
+                &chrom_lengths,
+                &chrom_regions,
+                window,
+                &header_lines,
+                &cli.output,
+            )?;
+        }
+        _ => write_base_matrix(&sample_coverages, &chunk_jobs, &names, &header_lines, &cli.output)?,
+    }
+    println!("Wrote coverage matrix to {:?}", cli.output);
+
+    let mut combined_stats = ReadStatsCollector::default();
+    for stats in &sample_stats {
+        combined_stats.merge(stats.clone());
+    }
+    let read_stats: Vec<ReadStats> = sample_stats.into_iter().map(ReadStatsCollector::finish).collect();
+
+    let summary_path = summary_path(&cli.output);
+    write_sample_summary(&summary_path, inputs, &names, &sample_coverages, &read_stats, target_bases)?;
+    println!("Wrote per-sample summary to {:?}", summary_path);
+
+    if !cli.skip_all_plots {
+        println!("Plots are not generated in multi-sample mode");
+    }
+    if cli.bed.is_some() {
+        println!("Per-target summaries are not written in multi-sample mode; see {:?} for per-sample totals", summary_path);
+    }
+
+    Ok((combined_stats.finish(), header.clone()))
+}
+
+/// Column names for the inputs: the file stem, or the full path when stems collide
+fn sample_names(inputs: &[AlignmentInput]) -> Vec<String> {
+    let stems: Vec<String> = inputs
+        .iter()
+        .map(|input| {
+            input
+                .path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| input.path.display().to_string())
+        })
+        .collect();
+
+    stems
+        .iter()
+        .zip(inputs)
+        .map(|(stem, input)| {
+            if stems.iter().filter(|other| *other == stem).count() > 1 {
+                input.path.display().to_string()
+            } else {
+                stem.clone()
+            }
+        })
+        .collect()
+}
+
+/// Summary file written next to the matrix (`coverage.tsv` -> `coverage.summary.tsv`)
+fn summary_path(output: &Path) -> PathBuf {
+    output.with_extension("summary.tsv")
+}
+
+/// Chromosomes with coverage in any sample, in output order
+fn covered_chromosomes(sample_coverages: &[SampleCoverage]) -> Vec<&String> {
+    let chroms: BTreeSet<&String> = sample_coverages.iter().flat_map(|coverage| coverage.keys()).collect();
+    chroms.into_iter().collect()
+}
+
+/// Write one row per counted position of the chunk plan, in plan order
+///
+/// Positions no sample covers are written with depth 0, so the rows are the `target_bases`
+/// of the per-sample summary.
+fn write_base_matrix(
+    sample_coverages: &[SampleCoverage],
+    chunks: &[CoverageChunk],
+    names: &[String],
+    header_lines: &[String],
+    output_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = BufWriter::new(File::create(output_path)?);
+    for line in header_lines {
+        writeln!(out, "{}", line)?;
+    }
+    writeln!(out, "#chromosome\tposition\t{}", names.join("\t"))?;
+
+    for chunk in chunks {
+        let chrom = &chunk.chromosome;
+        let chrom_coverages: Vec<Option<&HashMap<u32, u32>>> =
+            sample_coverages.iter().map(|coverage| coverage.get(chrom)).collect();
+
+        for pos in chunk.start..chunk.end {
+            write!(out, "{}\t{}", chrom, pos)?;
+            for coverage in &chrom_coverages {
+                let count = coverage.and_then(|positions| positions.get(&pos)).copied().unwrap_or(0);
+                write!(out, "\t{}", count)?;
+            }
+            writeln!(out)?;
+        }
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Write one row per fixed-size window covered in any sample, with the mean depth per sample
+///
+/// Windows are 0-based half-open (`start`, `end`) and clipped to the chromosome length. The
+/// mean is over the bases of the window inside the counted `chrom_regions` (1-based half-open,
+/// as from `regions_for_chromosome`), so windows at target edges are not diluted.
+fn write_window_matrix(
+    sample_coverages: &[SampleCoverage],
+    names: &[String],
+    chrom_lengths: &HashMap<String, u32>,
+    chrom_regions: &HashMap<String, Vec<(u32, u32)>>,
+    window: u32,
+    header_lines: &[String],
+    output_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = BufWriter::new(File::create(output_path)?);
+    for line in header_lines {
+        writeln!(out, "{}", line)?;
+    }
+    writeln!(out, "#chromosome\tstart\tend\t{}", names.join("\t"))?;
+
+    for chrom in covered_chromosomes(sample_coverages) {
+        let chrom_length = chrom_lengths.get(chrom).copied().unwrap_or(u32::MAX);
+        let regions = chrom_regions.get(chrom).map(Vec::as_slice).unwrap_or_default();
+
+        // Sum depth per window and sample
+        let mut window_sums: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
+        for (sample, coverage) in sample_coverages.iter().enumerate() {
+            if let Some(positions) = coverage.get(chrom) {
+                for (&pos, &count) in positions {
+                    let sums = window_sums
+                        .entry((pos - 1) / window)
+                        .or_insert_with(|| vec![0; sample_coverages.len()]);
+                    sums[sample] += count as u64;
+                }
+            }
+        }
+
+        for (index, sums) in window_sums {
+            let start = index * window;
+            let end = std::cmp::min(start.saturating_add(window), chrom_length);
+            // Window positions are start + 1 ..= end in 1-based coordinates
+            let counted_bases: u64 = regions
+                .iter()
+                .map(|&(region_start, region_end)| {
+                    region_end.min(end.saturating_add(1)).saturating_sub(region_start.max(start + 1)) as u64
+                })
+                .sum();
+            let width = counted_bases.max(1) as f64;
+            write!(out, "{}\t{}\t{}", chrom, start, end)?;
+            for sum in sums {
+                write!(out, "\t{:.2}", sum as f64 / width)?;
+            }
+            writeln!(out)?;
+        }
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Write read and coverage statistics for each sample
+fn write_sample_summary(
+    path: &Path,
+    inputs: &[AlignmentInput],
+    names: &[String],
+    sample_coverages: &[SampleCoverage],
+    read_stats: &[ReadStats],
+    target_bases: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        "#sample\tpath\treads\tyield_bases\tn50\tmean_read_length\tmean_read_quality\ttarget_bases\tcovered_bases\tbreadth_pct\tmean_depth\tmean_depth_covered"
+    )?;
+
+    for (((name, input), coverage), stats) in names.iter().zip(inputs).zip(sample_coverages).zip(read_stats) {
+        let (total_depth, covered_bases) = coverage
+            .values()
+            .flat_map(|positions| positions.values())
+            .fold((0u64, 0u64), |(t, c), v| (t + *v as u64, c + 1));
+
+        let ratio = |numerator: f64, denominator: u64| {
+            if denominator > 0 { numerator / denominator as f64 } else { 0.0 }
+        };
+
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{:.2}\t{:.2}\t{}\t{}\t{:.2}\t{:.2}\t{:.2}",
+            name,
+            input.path.display(),
+            stats.num_reads,
+            stats.num_bases,
+            stats.n50,
+            stats.mean_len,
+            stats.mean_qual,
+            target_bases,
+            covered_bases,
+            ratio(covered_bases as f64 * 100.0, target_bases),
+            ratio(total_depth as f64, target_bases),
+            ratio(total_depth as f64, covered_bases),
+        )?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coverage(chrom: &str, positions: &[(u32, u32)]) -> SampleCoverage {
+        let mut coverage = HashMap::new();
+        coverage.insert(chrom.to_string(), positions.iter().copied().collect());
+        coverage
+    }
+
+    #[test]
+    fn test_base_matrix_writes_every_planned_position() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("matrix.tsv");
+        let samples = vec![coverage("chr1", &[(1, 2), (2, 3)]), coverage("chr1", &[(2, 5), (4, 1)])];
+        let names = vec!["a".to_string(), "b".to_string()];
+        let chunk = |chromosome: &str, start, end| CoverageChunk {
+            chromosome: chromosome.to_string(),
+            start,
+            end,
+            target_regions: None,
+            stats_starts: None,
+        };
+        // Position 3 and chr2 have no coverage in any sample
+        let chunks = vec![chunk("chr1", 1, 4), chunk("chr1", 4, 5), chunk("chr2", 1, 2)];
+
+        write_base_matrix(&samples, &chunks, &names, &["##exclude_flags=0x0".to_string()], &path).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            "##exclude_flags=0x0\n#chromosome\tposition\ta\tb\nchr1\t1\t2\t0\nchr1\t2\t3\t5\nchr1\t3\t0\t0\nchr1\t4\t0\t1\nchr2\t1\t0\t0\n"
+        );
+    }
+
+    #[test]
+    fn test_window_matrix_averages_and_clips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("matrix.tsv");
+        let samples = vec![coverage("chr1", &[(1, 4), (2, 4), (11, 6)])];
+        let names = vec!["a".to_string()];
+        let lengths = HashMap::from([("chr1".to_string(), 12)]);
+
+        let whole_chromosome = HashMap::from([("chr1".to_string(), vec![(1, 13)])]);
+
+        write_window_matrix(&samples, &names, &lengths, &whole_chromosome, 10, &[], &path).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "#chromosome\tstart\tend\ta\nchr1\t0\t10\t0.80\nchr1\t10\t12\t3.00\n");
+
+        // Only positions 1-2 and 11 are counted: means are over those bases
+        let targets = HashMap::from([("chr1".to_string(), vec![(1, 3), (11, 12)])]);
+        write_window_matrix(&samples, &names, &lengths, &targets, 10, &[], &path).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "#chromosome\tstart\tend\ta\nchr1\t0\t10\t4.00\nchr1\t10\t12\t6.00\n");
+    }
+
+    #[test]
+    fn test_summary_path() {
+        assert_eq!(summary_path(Path::new("out/coverage.tsv")), PathBuf::from("out/coverage.summary.tsv"));
+    }
+}
@@ -1,64 +1,118 @@
 // src/io/mod.rs
 // IO module for nanocov: BAM/BED reading, coverage writing
 
+pub mod accumulator;
+pub mod alignment;
+pub mod blocks;
+pub mod contigs;
 pub mod cramino;
+pub mod expr;
+pub mod filter;
+pub mod groups;
+pub mod matrix;
+pub mod targets;
 
 use crate::cli::Cli;
+use accumulator::{CoverageEvents, DiffArray};
+use alignment::{AlignmentInput, AlignmentReader, IndexedInput, ReaderCache};
+use blocks::{CoverageRules, MateOverlaps};
+use contigs::ContigNames;
+use filter::ReadFilter;
 
 // Functions for BAM/BED reading and coverage writing will be moved here from main.rs
 // (Implementations will be moved in the next step)
 
-use crate::utils::ReadStats;
-
-/// Calculate the reference span from a CIGAR string
-/// This properly handles different CIGAR operations to get the actual alignment length on the reference
-fn calculate_reference_span(cigar: &noodles_bam::record::Cigar) -> u32 {
-    cigar.iter()
-        .filter_map(|op_result| {
-            if let Ok(op) = op_result {
-                use noodles_sam::alignment::record::cigar::op::Kind;
-                match op.kind() {
-                    // Operations that consume reference sequence
-                    Kind::Match | 
-                    Kind::Deletion | 
-                    Kind::Skip | 
-                    Kind::SequenceMatch | 
-                    Kind::SequenceMismatch => {
-                        Some(op.len() as u32)
-                    }
-                    // Operations that don't consume reference: Insertion, SoftClip, HardClip, Pad
-                    Kind::Insertion | 
-                    Kind::SoftClip | 
-                    Kind::HardClip | 
-                    Kind::Pad => None,
-                }
-            } else {
-                None // Skip invalid CIGAR operations
-            }
-        })
-        .sum()
-}
+use crate::utils::{extract_read_stats, ReadStats, ReadStatsCollector};
+
+/// Run the selected coverage engine and return the read statistics of all inputs, with the
+/// header the engine read (standard input cannot be read again for it)
+///
+/// Read statistics are gathered in the coverage pass where that pass sees every record
+/// (see [`read_stats_in_pass`]); otherwise one separate pass collects them.
+///
+/// Coverage from several inputs (sharing one reference dictionary) is summed into one track.
+/// Every engine counts only the alignments passing the same [`ReadFilter`], and credits
+/// their aligned blocks following the same [`CoverageRules`].
+pub fn run_coverage(
+    cli: &Cli,
+    inputs: &[AlignmentInput],
+    filter: &ReadFilter,
+) -> Result<(ReadStats, noodles_sam::Header), Box<dyn std::error::Error>> {
+    let rules = CoverageRules::from_cli(cli);
 
-pub fn run_coverage(cli: &Cli, read_stats: Option<ReadStats>) -> Result<(), Box<dyn std::error::Error>> {
     // Choose the appropriate coverage calculation mode
-    if should_use_streaming_mode(cli)? {
+    if cli.split_by.is_some() {
+        // Separate coverage and read stats per read group or barcode
+        groups::run_coverage_by_group(cli, inputs, filter, &rules)
+    } else if cli.multi_sample {
+        // One coverage column per input instead of a summed track
+        matrix::run_coverage_multi_sample(cli, inputs, filter, &rules)
+    } else if use_sweep_mode(cli, inputs) {
+        // Single sequential pass for unindexed files and standard input
+        run_coverage_sweep(cli, inputs, filter, &rules)
+    } else if should_use_streaming_mode(cli, inputs)? {
         // Use streaming mode for very large files
-        run_coverage_streaming(cli, read_stats)
+        run_coverage_streaming(cli, inputs, filter, &rules)
     } else {
         // Use enhanced parallel chunked mode for better performance
-        run_coverage_parallel_chunked(cli, read_stats)
+        run_coverage_parallel_chunked(cli, inputs, filter, &rules)
+    }
+}
+
+/// Whether indexed engines can collect read statistics while computing coverage
+///
+/// This needs region queries over whole chromosomes (no BED restriction), so that every
+/// placed record is returned, and BAM input, whose unplaced unmapped reads can be queried.
+fn read_stats_in_pass(cli: &Cli, inputs: &[AlignmentInput]) -> bool {
+    cli.bed.is_none()
+        && cli.regions.is_empty()
+        && cli.chrom_bed.is_none()
+        && cli.exclude_bed.is_none()
+        && inputs.iter().all(|input| input.format == alignment::AlignmentFormat::Bam)
+}
+
+/// Add the unplaced unmapped reads of an input, which no region query returns
+fn collect_unplaced_read_stats(
+    indexed: &IndexedInput,
+    filter: &ReadFilter,
+    stats: &mut ReadStatsCollector,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = indexed.input.open_sequential()?;
+    for result in reader.query_unplaced(&indexed.index)? {
+        stats.add_record_with_filter(result?.as_ref(), filter)?;
     }
+    Ok(())
+}
+
+/// Coordinate model of all coverage outputs, recorded in their header
+const COORDINATES_HEADER: &str =
+    "##coordinates=positions are 1-based; BED input and window/target start/end are 0-based half-open";
+
+/// `##` lines at the top of coverage outputs, recording how coverage was counted
+fn output_header_lines(filter: &ReadFilter, rules: &CoverageRules) -> Vec<String> {
+    let mut lines = vec![COORDINATES_HEADER.to_string()];
+    lines.extend(filter.header_lines());
+    lines.extend(rules.header_lines());
+    lines
+}
+
+/// Determine if the inputs have to be read sequentially (no index, stdin, or --sweep)
+pub fn use_sweep_mode(cli: &Cli, inputs: &[AlignmentInput]) -> bool {
+    cli.sweep || inputs.iter().any(|input| input.index_path().is_none())
 }
 
 /// Determine if we should use streaming mode based on file size and available memory
-fn should_use_streaming_mode(cli: &Cli) -> Result<bool, Box<dyn std::error::Error>> {
+fn should_use_streaming_mode(cli: &Cli, inputs: &[AlignmentInput]) -> Result<bool, Box<dyn std::error::Error>> {
     // Force streaming if explicitly requested
     if cli.force_streaming {
         return Ok(true);
     }
     
-    let bam_metadata = std::fs::metadata(&cli.input)?;
-    let file_size_mb = bam_metadata.len() / (1024 * 1024);
+    let mut total_size = 0;
+    for input in inputs {
+        total_size += std::fs::metadata(&input.path)?.len();
+    }
+    let file_size_mb = total_size / (1024 * 1024);
     
     // Use custom memory limit or default to 500MB
     let memory_limit_mb = cli.memory_limit_mb.unwrap_or(500);
@@ -67,47 +121,82 @@ fn should_use_streaming_mode(cli: &Cli) -> Result<bool, Box<dyn std::error::Erro
     Ok(file_size_mb > memory_limit_mb)
 }
 
-/// Memory-efficient streaming approach for large BAM files
-fn run_coverage_streaming(cli: &Cli, read_stats: Option<ReadStats>) -> Result<(), Box<dyn std::error::Error>> {
+/// Memory-efficient streaming approach for large alignment files
+fn run_coverage_streaming(
+    cli: &Cli,
+    inputs: &[AlignmentInput],
+    filter: &ReadFilter,
+    rules: &CoverageRules,
+) -> Result<(ReadStats, noodles_sam::Header), Box<dyn std::error::Error>> {
     use std::collections::HashMap;
     use std::fs::File;
     use std::io::{Write, BufWriter};
-    use noodles_bam as bam;
 
-    println!("Using memory-efficient streaming mode for large BAM file");
+    println!("Using memory-efficient streaming mode for large alignment file");
 
-    // Open BAM file
-    let mut reader = bam::io::reader::Builder::default().build_from_path(&cli.input)?;
-    let header = reader.read_header()?;
+    // Read each header and index once (all inputs share the same reference dictionary);
+    // one reader per input is reused for every chromosome query
+    let indexed_inputs = inputs
+        .iter()
+        .map(AlignmentInput::load_indexed)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut readers = ReaderCache::new(inputs.len());
+    let header = &indexed_inputs[0].header;
     let reference_sequences = header.reference_sequences();
 
     // Parse BED files
-    let bed_regions = if let Some(bed_path) = &cli.bed {
-        Some(nanocov::parse_bed(bed_path)?)
-    } else {
-        None
-    };
-
-    let chrom_bed_regions = if let Some(chrom_bed_path) = &cli.chrom_bed {
-        Some(nanocov::parse_bed(chrom_bed_path)?)
-    } else {
-        None
-    };
+    let contigs = ContigNames::new(cli, header)?;
+    let bed_regions = load_targets(cli, &contigs)?;
+    let chrom_bed_regions = load_bed(cli.chrom_bed.as_ref(), &contigs)?;
+    let exclude_regions = load_bed(cli.exclude_bed.as_ref(), &contigs)?;
 
     // Create output file with buffered writer
     let mut out = BufWriter::new(File::create(&cli.output)?);
+    for line in output_header_lines(filter, rules) {
+        writeln!(out, "{}", line)?;
+    }
     writeln!(out, "#chromosome\tposition\tcount")?;
 
     let mut global_avg_sum = 0.0;
     let mut global_avg_count = 0;
     let mut chrom_coverages: HashMap<String, HashMap<u32, u32>> = HashMap::new();
 
+    // Whole-chromosome queries see every placed record once, so read stats come for free
+    let mut stats_collector = if read_stats_in_pass(cli, inputs) {
+        Some(ReadStatsCollector::default())
+    } else {
+        None
+    };
+
     // Process each chromosome individually to save memory
-    for (chrom_name, _) in reference_sequences.iter() {
+    for (chrom_name, ref_seq) in reference_sequences.iter() {
         let chrom = chrom_name.to_string();
         println!("Processing chromosome: {}", chrom);
 
-        let coverage = process_chromosome_streaming(&cli.input, &chrom, &bed_regions, &chrom_bed_regions)?;
+        let regions = regions_for_chromosome(
+            &chrom,
+            ref_seq.length().get() as u32,
+            &bed_regions,
+            &chrom_bed_regions,
+            &exclude_regions,
+        );
+
+        // Sum coverage for this chromosome over all inputs
+        let mut coverage: HashMap<u32, u32> = HashMap::new();
+        for (i, indexed) in indexed_inputs.iter().enumerate() {
+            let chrom_coverage = process_chromosome_streaming(
+                readers.get(i, indexed.input)?,
+                indexed,
+                &chrom,
+                &regions,
+                filter,
+                rules,
+                stats_collector.as_mut(),
+            )?;
+            for (pos, count) in chrom_coverage {
+                *coverage.entry(pos).or_insert(0) += count;
+            }
+        }
         
         if !coverage.is_empty() {
             // Calculate chromosome average
@@ -131,6 +220,7 @@ fn run_coverage_streaming(cli: &Cli, read_stats: Option<ReadStats>) -> Result<()
     }
 
     out.flush()?;
+    targets::write_target_summary_for(cli, &contigs, &chrom_coverages, &cli.output, &output_header_lines(filter, rules))?;
 
     // Print global average
     if global_avg_count > 0 {
@@ -138,85 +228,80 @@ fn run_coverage_streaming(cli: &Cli, read_stats: Option<ReadStats>) -> Result<()
         println!("Global average coverage: {:.2}", global_avg);
     }
 
+    let read_stats = match stats_collector {
+        Some(mut stats) => {
+            for indexed in &indexed_inputs {
+                collect_unplaced_read_stats(indexed, filter, &mut stats)?;
+            }
+            stats.finish()
+        }
+        None => extract_read_stats(inputs, filter)?,
+    };
+
     // Generate plots with reduced memory usage (if not disabled)
     if !cli.skip_all_plots {
-        generate_plots_from_stored_coverage(cli, &chrom_coverages, read_stats.as_ref())?;
+        generate_plots_from_stored_coverage(cli, &contigs, &chrom_coverages, Some(&read_stats))?;
     } else {
         println!("Skipping plot generation as requested (--no-plots)");
     }
 
-    Ok(())
+    Ok((read_stats, header.clone()))
 }
 
-/// Process a single chromosome and return its coverage data
+/// Process the regions (1-based half-open) of a single chromosome and return its coverage data
+///
+/// `reader` is a single-threaded reader of `indexed.input`: every region is a seek, which
+/// would discard the read-ahead of multithreaded decompression. When `stats` is given, every
+/// queried record is added to it; callers only pass it when the chromosome is queried as a
+/// single whole-chromosome region.
 fn process_chromosome_streaming(
-    bam_path: &std::path::Path,
+    reader: &mut AlignmentReader,
+    indexed: &IndexedInput,
     chrom: &str,
-    bed_regions: &Option<std::collections::HashMap<String, Vec<(u32, u32)>>>,
-    chrom_bed_regions: &Option<std::collections::HashMap<String, Vec<(u32, u32)>>>,
+    regions: &[(u32, u32)],
+    filter: &ReadFilter,
+    rules: &CoverageRules,
+    mut stats: Option<&mut ReadStatsCollector>,
 ) -> Result<std::collections::HashMap<u32, u32>, Box<dyn std::error::Error>> {
     use std::collections::HashMap;
-    use std::fs::File;
-    use std::io::BufReader;
-    use noodles_bam as bam;
     use noodles_core::Region;
     use noodles_core::Position;
 
-    let mut reader = bam::io::Reader::new(BufReader::new(File::open(bam_path)?));
-    let mut bai_reader = noodles_bam::bai::Reader::new(File::open(&bam_path.with_extension("bam.bai"))?);
-    let index = bai_reader.read_index()?;
-    let header = reader.read_header()?;
-    let reference_sequences = header.reference_sequences();
+    let header = &indexed.header;
 
     let mut coverage: HashMap<u32, u32> = HashMap::new();
+    let mut events = CoverageEvents::default();
 
-    // Determine regions to process for this chromosome
-    let regions_to_process = if let Some(bed) = bed_regions {
-        bed.get(chrom).cloned().unwrap_or_default()
-    } else if let Some(chrom_bed) = chrom_bed_regions {
-        chrom_bed.get(chrom).cloned().unwrap_or_default()
-    } else {
-        // Process entire chromosome
-        if let Some((_, ref_seq)) = reference_sequences.iter().find(|(k, _)| k.to_string() == chrom) {
-            vec![(1, ref_seq.length().get() as u32)]
-        } else {
-            return Ok(coverage);
-        }
-    };
-
-    // Process each region
-    for (start, end) in regions_to_process {
+    // Process each region; queries take the last position of the region
+    for &(start, end) in regions {
         let region = Region::new(
             chrom.to_string(),
             Position::try_from(start as usize)
                 .map_err(|e| format!("Invalid start position {}: {}", start, e))?
-                ..=Position::try_from(end as usize)
+                ..=Position::try_from((end - 1) as usize)
                     .map_err(|e| format!("Invalid end position {}: {}", end, e))?,
         );
 
-        let query = reader.query(&header, &index, &region)?;
+        let query = reader.query(header, &indexed.index, &region)?;
+        let mut mates = MateOverlaps::default();
         for result in query {
             let record = result?;
-            if record.flags().is_unmapped() {
+            let passed = match stats.as_deref_mut() {
+                Some(stats) => stats.add_record_with_filter(record.as_ref(), filter)? && filter.passes_flags(record.as_ref())?,
+                None => filter.passes(record.as_ref())?,
+            };
+            if !passed {
                 continue;
             }
 
-            let start_pos = match record.alignment_start() {
-                Some(Ok(pos)) => pos.get() as u32,
-                _ => continue,
-            };
-
-            let len = calculate_reference_span(&record.cigar());
-            
-            // Update coverage for this alignment
-            for pos in start_pos..start_pos + len {
-                if pos >= start && pos <= end {
-                    *coverage.entry(pos).or_insert(0) += 1;
-                }
+            // Update coverage for each aligned block, clipped to the region
+            for (block_start, block_end) in rules.counted_blocks(header, record.as_ref(), &mut mates)? {
+                events.add(std::cmp::max(block_start, start), std::cmp::min(block_end, end));
             }
         }
     }
 
+    events.add_into(&mut coverage);
     Ok(coverage)
 }
 
@@ -241,6 +326,7 @@ fn write_chromosome_coverage(
 /// Generate plots with memory-efficient approach
 fn generate_plots_from_stored_coverage(
     cli: &Cli,
+    contigs: &ContigNames,
     chrom_coverages: &std::collections::HashMap<String, std::collections::HashMap<u32, u32>>,
     read_stats: Option<&ReadStats>,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -282,9 +368,9 @@ fn generate_plots_from_stored_coverage(
     if chrom_coverages.len() > 1 {
         let multi_plot_path = output_dir.join(format!("{}.multi_chrom.{}", output_stem, file_format));
         
-        // Convert to the expected format for plot_all_chromosomes
+        // Convert to the expected format for plot_all_chromosomes, labelled with chr-style names
         let chrom_refs: std::collections::HashMap<String, &std::collections::HashMap<u32, u32>> = 
-            chrom_coverages.iter().map(|(k, v)| (k.clone(), v)).collect();
+            chrom_coverages.iter().map(|(k, v)| (contigs.display_name(k), v)).collect();
         
         // Get current theme
         let theme = unsafe { crate::plotting::CURRENT_THEME };
@@ -302,35 +388,26 @@ fn generate_plots_from_stored_coverage(
 }
 
 /// Original in-memory approach for smaller files
-fn run_coverage_in_memory(cli: &Cli, read_stats: Option<ReadStats>) -> Result<(), Box<dyn std::error::Error>> {
+fn run_coverage_in_memory(cli: &Cli, input: &AlignmentInput, read_stats: Option<ReadStats>) -> Result<(), Box<dyn std::error::Error>> {
     use std::collections::HashMap;
     use std::fs::File;
     use std::io::Write;
-    use std::io::BufReader;
-    use noodles_bam as bam;
     use noodles_core::Region;
     use noodles_core::Position;
-    use nanocov::parse_bed;
     use rayon::prelude::*;
 
-    // Open BAM file using noodles_bam::io::reader::Builder
-    let mut reader = bam::io::reader::Builder::default().build_from_path(&cli.input)?;
+    let filter = ReadFilter::from_cli(cli);
+    let rules = CoverageRules::from_cli(cli);
+
+    // Open alignment file
+    let mut reader = input.open()?;
     let header = reader.read_header()?;
     let reference_sequences = header.reference_sequences();
 
-    // Parse BED file if provided; returns Option<HashMap<String, Vec<(u32, u32)>>> mapping chrom to regions
-    let bed_regions = if let Some(bed_path) = &cli.bed {
-        Some(parse_bed(bed_path)?)
-    } else {
-        None
-    };
-
-    // Parse chrom_bed file if provided; returns Option<HashMap<String, Vec<(u32, u32)>>> mapping chrom to full ranges
-    let chrom_bed_regions = if let Some(chrom_bed_path) = &cli.chrom_bed {
-        Some(parse_bed(chrom_bed_path)?)
-    } else {
-        None
-    };
+    // Parse BED files if provided (1-based half-open regions by chromosome)
+    let contigs = ContigNames::new(cli, &header)?;
+    let bed_regions = load_targets(cli, &contigs)?;
+    let chrom_bed_regions = load_bed(cli.chrom_bed.as_ref(), &contigs)?;
 
     let coverage_results: Vec<(HashMap<String, HashMap<u32, u32>>, HashMap<String, f64>)> = 
         if let Some(ref bed) = bed_regions {
@@ -343,39 +420,35 @@ fn run_coverage_in_memory(cli: &Cli, read_stats: Option<ReadStats>) -> Result<()
             }
             // Parallelize over regions
             region_jobs.par_iter().map(|(chrom, start, end)| {
-                let mut reader = bam::io::Reader::new(BufReader::new(File::open(&cli.input).unwrap()));
-                let mut bai_reader = noodles_bam::bai::Reader::new(File::open(&cli.input.with_extension("bam.bai")).unwrap());
-                let index = bai_reader.read_index().unwrap();
+                let mut reader = input.open().unwrap();
+                let index = input.read_index().unwrap();
                 let header = reader.read_header().unwrap();
                 let reference_sequences = header.reference_sequences();
                 let ref_names: Vec<String> = reference_sequences.keys().map(|b| b.to_string()).collect();
                 let region = Region::new(
                     chrom.clone(),
-                    Position::try_from((*start + 1) as usize).unwrap()..=Position::try_from(*end as usize).unwrap()
+                    Position::try_from(*start as usize).unwrap()..=Position::try_from((*end - 1) as usize).unwrap()
                 );
                 let mut local_coverage: HashMap<String, HashMap<u32, u32>> = HashMap::new();
                 let mut local_averages: HashMap<String, f64> = HashMap::new();
                 let query = reader.query(&header, &index, &region).unwrap();
                 for result in query {
                     let record = result.unwrap();
-                    if record.flags().is_unmapped() {
+                    if !filter.passes(record.as_ref()).unwrap() {
                         continue;
                     }
-                    let ref_id = match record.reference_sequence_id() {
+                    let ref_id = match record.reference_sequence_id(&header) {
                         Some(Ok(id)) => id,
                         _ => continue,
                     };
                     let ref_name = ref_names.get(ref_id)
                         .map(|name| name.clone())
                         .unwrap_or_else(|| "unknown".to_string());
-                    let start = match record.alignment_start() {
-                        Some(Ok(pos)) => pos.get() as u32,
-                        _ => continue,
-                    };
-                    let len = calculate_reference_span(&record.cigar());
                     let region_coverage = local_coverage.entry(ref_name.clone()).or_default();
-                    for pos in start..start + len {
-                        *region_coverage.entry(pos).or_insert(0) += 1;
+                    for (block_start, block_end) in rules.blocks(record.as_ref()).unwrap() {
+                        for pos in block_start.max(*start)..block_end.min(*end) {
+                            *region_coverage.entry(pos).or_insert(0) += 1;
+                        }
                     }
                 }
                 for (ref_name, region_coverage) in &local_coverage {
@@ -396,39 +469,35 @@ fn run_coverage_in_memory(cli: &Cli, read_stats: Option<ReadStats>) -> Result<()
                 }
             }
             chrom_jobs.par_iter().map(|(chrom, start, end)| {
-                let mut reader = bam::io::Reader::new(BufReader::new(File::open(&cli.input).unwrap()));
-                let mut bai_reader = noodles_bam::bai::Reader::new(File::open(&cli.input.with_extension("bam.bai")).unwrap());
-                let index = bai_reader.read_index().unwrap();
+                let mut reader = input.open().unwrap();
+                let index = input.read_index().unwrap();
                 let header = reader.read_header().unwrap();
                 let reference_sequences = header.reference_sequences();
                 let ref_names: Vec<String> = reference_sequences.keys().map(|b| b.to_string()).collect();
                 let region = Region::new(
                     chrom.clone(),
-                    Position::try_from((*start + 1) as usize).unwrap()..=Position::try_from(*end as usize).unwrap()
+                    Position::try_from(*start as usize).unwrap()..=Position::try_from((*end - 1) as usize).unwrap()
                 );
                 let mut local_coverage: HashMap<String, HashMap<u32, u32>> = HashMap::new();
                 let mut local_averages: HashMap<String, f64> = HashMap::new();
                 let query = reader.query(&header, &index, &region).unwrap();
                 for result in query {
                     let record = result.unwrap();
-                    if record.flags().is_unmapped() {
+                    if !filter.passes(record.as_ref()).unwrap() {
                         continue;
                     }
-                    let ref_id = match record.reference_sequence_id() {
+                    let ref_id = match record.reference_sequence_id(&header) {
                         Some(Ok(id)) => id,
                         _ => continue,
                     };
                     let ref_name = ref_names.get(ref_id)
                         .map(|name| name.clone())
                         .unwrap_or_else(|| "unknown".to_string());
-                    let start = match record.alignment_start() {
-                        Some(Ok(pos)) => pos.get() as u32,
-                        _ => continue,
-                    };
-                    let len = calculate_reference_span(&record.cigar());
                     let region_coverage = local_coverage.entry(ref_name.clone()).or_default();
-                    for pos in start..start + len {
-                        *region_coverage.entry(pos).or_insert(0) += 1;
+                    for (block_start, block_end) in rules.blocks(record.as_ref()).unwrap() {
+                        for pos in block_start.max(*start)..block_end.min(*end) {
+                            *region_coverage.entry(pos).or_insert(0) += 1;
+                        }
                     }
                 }
                 for (ref_name, region_coverage) in &local_coverage {
@@ -445,9 +514,8 @@ fn run_coverage_in_memory(cli: &Cli, read_stats: Option<ReadStats>) -> Result<()
             let ref_names: Vec<String> = reference_sequences.keys().map(|b| b.to_string()).collect();
             let chrom_jobs: Vec<String> = ref_names.clone();
             chrom_jobs.par_iter().map(|chrom| {
-                let mut reader = bam::io::Reader::new(BufReader::new(File::open(&cli.input).unwrap()));
-                let mut bai_reader = noodles_bam::bai::Reader::new(File::open(&cli.input.with_extension("bam.bai")).unwrap());
-                let index = bai_reader.read_index().unwrap();
+                let mut reader = input.open().unwrap();
+                let index = input.read_index().unwrap();
                 let header = reader.read_header().unwrap();
                 let reference_sequences = header.reference_sequences();
                 // Find the reference sequence for this chromosome
@@ -462,24 +530,21 @@ fn run_coverage_in_memory(cli: &Cli, read_stats: Option<ReadStats>) -> Result<()
                 let query = reader.query(&header, &index, &region).unwrap();
                 for result in query {
                     let record = result.unwrap();
-                    if record.flags().is_unmapped() {
+                    if !filter.passes(record.as_ref()).unwrap() {
                         continue;
                     }
-                    let ref_id = match record.reference_sequence_id() {
+                    let ref_id = match record.reference_sequence_id(&header) {
                         Some(Ok(id)) => id,
                         _ => continue,
                     };
                     let ref_name = ref_names.get(ref_id)
                         .map(|name| name.clone())
                         .unwrap_or_else(|| "unknown".to_string());
-                    let start = match record.alignment_start() {
-                        Some(Ok(pos)) => pos.get() as u32,
-                        _ => continue,
-                    };
-                    let len = calculate_reference_span(&record.cigar());
                     let region_coverage = local_coverage.entry(ref_name.clone()).or_default();
-                    for pos in start..start + len {
-                        *region_coverage.entry(pos).or_insert(0) += 1;
+                    for (start, end) in rules.blocks(record.as_ref()).unwrap() {
+                        for pos in start..end {
+                            *region_coverage.entry(pos).or_insert(0) += 1;
+                        }
                     }
                 }
                 for (ref_name, region_coverage) in &local_coverage {
@@ -511,6 +576,9 @@ fn run_coverage_in_memory(cli: &Cli, read_stats: Option<ReadStats>) -> Result<()
     // Write per-position coverage to a file (TSV format) using BufWriter and parallel formatting
     use std::io::BufWriter;
     let mut out = BufWriter::new(File::create(&cli.output)?);
+    for line in output_header_lines(&filter, &rules) {
+        writeln!(out, "{}", line)?;
+    }
     writeln!(out, "#chromosome\tposition\tcount")?;
     // Parallelize formatting of coverage lines per chromosome
     let chrom_blocks: Vec<(String, String)> = coverage.par_iter()
@@ -639,15 +707,12 @@ use std::collections::HashMap;
 use rayon::prelude::*;
 
 /// Enhanced parallel coverage calculation with efficient BAM index usage and chunking
-fn run_coverage_parallel_chunked(cli: &Cli, read_stats: Option<ReadStats>) -> Result<(), Box<dyn std::error::Error>> {
-    use std::fs::File;
-    use std::io::{Write, BufWriter};
-    use std::io::BufReader;
-    use noodles_bam as bam;
-    use noodles_core::Region;
-    use noodles_core::Position;
-    use nanocov::parse_bed;
-
+fn run_coverage_parallel_chunked(
+    cli: &Cli,
+    inputs: &[AlignmentInput],
+    filter: &ReadFilter,
+    rules: &CoverageRules,
+) -> Result<(ReadStats, noodles_sam::Header), Box<dyn std::error::Error>> {
     println!("Using enhanced parallel coverage calculation with chunking");
 
     // Set thread pool size
@@ -658,55 +723,139 @@ fn run_coverage_parallel_chunked(cli: &Cli, read_stats: Option<ReadStats>) -> Re
             .map_err(|e| format!("Failed to set thread pool: {}", e))?;
     }
 
-    // Open BAM file and read header once
-    let mut reader = bam::io::reader::Builder::default().build_from_path(&cli.input)?;
-    let header = reader.read_header()?;
+    // Read each header and index once; they are shared by all chunk workers
+    let indexed_inputs = inputs
+        .iter()
+        .map(AlignmentInput::load_indexed)
+        .collect::<Result<Vec<_>, _>>()?;
+    // All inputs share the same reference dictionary
+    let header = &indexed_inputs[0].header;
 
     // Parse BED files
-    let bed_regions = if let Some(bed_path) = &cli.bed {
-        Some(parse_bed(bed_path)?)
-    } else {
-        None
-    };
-
-    let chrom_bed_regions = if let Some(chrom_bed_path) = &cli.chrom_bed {
-        Some(parse_bed(chrom_bed_path)?)
-    } else {
-        None
-    };
+    let contigs = ContigNames::new(cli, header)?;
+    let bed_regions = load_targets(cli, &contigs)?;
+    let chrom_bed_regions = load_bed(cli.chrom_bed.as_ref(), &contigs)?;
+    let exclude_regions = load_bed(cli.exclude_bed.as_ref(), &contigs)?;
 
     // Create chunk jobs for parallel processing
-    let chunk_jobs = create_chunk_jobs(&header, &bed_regions, &chrom_bed_regions, cli.chunk_size)?;
+    let collect_stats = read_stats_in_pass(cli, inputs);
+    let chunk_jobs = create_chunk_jobs(header, &bed_regions, &chrom_bed_regions, &exclude_regions, cli.chunk_size, collect_stats)?;
     
     println!("Created {} chunk jobs for parallel processing", chunk_jobs.len());
 
-    // Process chunks in parallel with shared BAM access
-    let coverage_results: Vec<HashMap<String, HashMap<u32, u32>>> = chunk_jobs
-        .par_iter()
-        .map(|chunk| {
-            process_chunk_efficiently(&cli.input, chunk)
-                .unwrap_or_else(|e| {
-                    eprintln!("Warning: Failed to process chunk {:?}: {}", chunk, e);
-                    HashMap::new()
-                })
-        })
-        .collect();
+    // Process chunks of every input in parallel with shared BAM access
+    let coverage_results = process_chunks_parallel(&indexed_inputs, &chunk_jobs, filter, rules);
 
     // Merge results from all chunks
     let mut merged_coverage: HashMap<String, HashMap<u32, u32>> = HashMap::new();
-    let mut per_chrom_averages: Vec<f64> = Vec::new();
+    let mut stats_collector = ReadStatsCollector::default();
     
-    for chunk_coverage in coverage_results {
+    for result in coverage_results {
+        let (_, chunk_coverage, chunk_stats) = result?;
         for (chrom, positions) in chunk_coverage {
             let chrom_coverage = merged_coverage.entry(chrom.clone()).or_default();
             for (pos, count) in positions {
                 *chrom_coverage.entry(pos).or_insert(0) += count;
             }
         }
+        stats_collector.merge(chunk_stats);
     }
 
+    let read_stats = if collect_stats {
+        for indexed in &indexed_inputs {
+            collect_unplaced_read_stats(indexed, filter, &mut stats_collector)?;
+        }
+        stats_collector.finish()
+    } else {
+        extract_read_stats(inputs, filter)?
+    };
+
+    report_coverage(cli, &contigs, &merged_coverage, &cli.output, &output_header_lines(filter, rules), None, Some(&read_stats))?;
+    Ok((read_stats, header.clone()))
+}
+
+/// Single-pass coverage over all records in file order
+///
+/// Works without an index (and on standard input) by accumulating coverage per
+/// reference sequence from the header. Inputs must be coordinate-sorted: the coverage of a
+/// reference sequence is added up when the next one starts, and an input going back to an
+/// earlier reference sequence is an error. Read statistics are gathered in the same pass.
+/// Inputs are read one after another into the same coverage.
+fn run_coverage_sweep(
+    cli: &Cli,
+    inputs: &[AlignmentInput],
+    filter: &ReadFilter,
+    rules: &CoverageRules,
+) -> Result<(ReadStats, noodles_sam::Header), Box<dyn std::error::Error>> {
+    println!("Using single-pass sequential sweep (no index required)");
+
+    let mut reader = inputs[0].open_sequential()?;
+    let header = reader.read_header()?;
+
+    let contigs = ContigNames::new(cli, &header)?;
+    let ref_regions = ReferenceRegions::new(cli, &header, &contigs)?;
+
+    let mut merged_coverage = CoverageMap::new();
+    let mut events = CoverageEvents::default();
+    let mut stats_collector = ReadStatsCollector::default();
+    let mut mates = MateOverlaps::default();
+
+    for (i, input) in inputs.iter().enumerate() {
+        if i > 0 {
+            reader = input.open_sequential()?;
+            reader.read_header()?;
+        }
+
+        // Only the events of the current reference sequence are kept: the input is
+        // coordinate-sorted, so a reference sequence is complete once the next one starts
+        let mut order = ReferenceOrder::default();
+        for result in reader.records(&header) {
+            let record = result?;
+            let passed = stats_collector.add_record_with_filter(record.as_ref(), filter)?;
+
+            let ref_id = match record.reference_sequence_id(&header) {
+                Some(Ok(id)) => id,
+                _ => continue,
+            };
+            if let Some(done) = order.advance(ref_id, &ref_regions, input)? {
+                ref_regions.flush(&mut events, done, &mut merged_coverage);
+            }
+
+            if !passed || !filter.passes_flags(record.as_ref())? {
+                continue;
+            }
+
+            let blocks = rules.counted_blocks(&header, record.as_ref(), &mut mates)?;
+            ref_regions.add_blocks(&mut events, ref_id, &blocks);
+        }
+        if let Some(last) = order.current {
+            ref_regions.flush(&mut events, last, &mut merged_coverage);
+        }
+    }
+
+    let read_stats = stats_collector.finish();
+    report_coverage(cli, &contigs, &merged_coverage, &cli.output, &output_header_lines(filter, rules), None, Some(&read_stats))?;
+
+    Ok((read_stats, header))
+}
+
+/// Print per-chromosome averages, write the coverage TSV (and per-target summary) to
+/// `output` and generate plots
+///
+/// `label` (e.g. a read group) is added to the per-chromosome plot titles.
+fn report_coverage(
+    cli: &Cli,
+    contigs: &ContigNames,
+    merged_coverage: &HashMap<String, HashMap<u32, u32>>,
+    output: &std::path::Path,
+    header_lines: &[String],
+    label: Option<&str>,
+    read_stats: Option<&ReadStats>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut per_chrom_averages: Vec<f64> = Vec::new();
+
     // Calculate per-chromosome averages
-    for (chrom, positions) in &merged_coverage {
+    for (chrom, positions) in merged_coverage {
         let (total, count) = positions.values().fold((0u64, 0u64), |(t, c), v| (t + *v as u64, c + 1));
         if count > 0 {
             let avg = total as f64 / count as f64;
@@ -716,11 +865,12 @@ fn run_coverage_parallel_chunked(cli: &Cli, read_stats: Option<ReadStats>) -> Re
     }
 
     // Write output using parallel formatting
-    write_coverage_parallel(&merged_coverage, &cli.output)?;
+    write_coverage_parallel(merged_coverage, header_lines, output)?;
+    targets::write_target_summary_for(cli, contigs, merged_coverage, output, header_lines)?;
 
     // Generate plots if requested
     if !cli.skip_all_plots {
-        generate_plots_from_coverage_map(cli, &merged_coverage, read_stats.as_ref())?;
+        generate_plots_from_coverage_map(cli, contigs, merged_coverage, output, label, read_stats)?;
     }
 
     // Print summary
@@ -732,6 +882,9 @@ fn run_coverage_parallel_chunked(cli: &Cli, read_stats: Option<ReadStats>) -> Re
     Ok(())
 }
 
+/// Coverage per chromosome: chromosome -> position -> depth
+type CoverageMap = HashMap<String, HashMap<u32, u32>>;
+
 /// Chunk definition for parallel processing
 #[derive(Debug, Clone)]
 struct CoverageChunk {
@@ -739,6 +892,7 @@ struct CoverageChunk {
     start: u32,
     end: u32,
     target_regions: Option<Vec<(u32, u32)>>, // BED regions within this chunk
+    stats_starts: Option<(u32, u32)>, // Alignment starts whose records count towards read stats
 }
 
 /// Create optimized chunk jobs for parallel processing
@@ -746,7 +900,9 @@ fn create_chunk_jobs(
     header: &noodles_sam::Header,
     bed_regions: &Option<HashMap<String, Vec<(u32, u32)>>>,
     chrom_bed_regions: &Option<HashMap<String, Vec<(u32, u32)>>>,
+    exclude_regions: &Option<HashMap<String, Vec<(u32, u32)>>>,
     chunk_size: usize,
+    collect_stats: bool,
 ) -> Result<Vec<CoverageChunk>, Box<dyn std::error::Error>> {
     let mut chunks = Vec::new();
     let chunk_size = chunk_size as u32;
@@ -757,14 +913,8 @@ fn create_chunk_jobs(
         let chrom_length = ref_seq.length().get() as u32;
 
         // Determine what regions to process for this chromosome
-        let regions_to_process = if let Some(bed) = bed_regions {
-            bed.get(&chrom).cloned().unwrap_or_default()
-        } else if let Some(chrom_bed) = chrom_bed_regions {
-            chrom_bed.get(&chrom).cloned().unwrap_or_default()
-        } else {
-            // Process entire chromosome
-            vec![(1, chrom_length)]
-        };
+        let regions_to_process =
+            regions_for_chromosome(&chrom, chrom_length, bed_regions, chrom_bed_regions, exclude_regions);
 
         if regions_to_process.is_empty() {
             continue;
@@ -784,11 +934,22 @@ fn create_chunk_jobs(
                     None
                 };
 
+                // Each record is counted for read stats by the chunk holding its start;
+                // the first and last chunks of a chromosome extend to its ends
+                let stats_starts = if collect_stats {
+                    let first = if current_start == region_start { 0 } else { current_start };
+                    let last = if chunk_end == region_end { u32::MAX } else { chunk_end };
+                    Some((first, last))
+                } else {
+                    None
+                };
+
                 chunks.push(CoverageChunk {
                     chromosome: chrom.clone(),
                     start: current_start,
                     end: chunk_end,
                     target_regions: chunk_target_regions,
+                    stats_starts,
                 });
 
                 current_start = chunk_end;
@@ -799,88 +960,427 @@ fn create_chunk_jobs(
     Ok(chunks)
 }
 
-/// Process a single chunk efficiently with optimized BAM access
+/// Regions of a BED file by chromosome
+type BedRegions = HashMap<String, Vec<(u32, u32)>>;
+
+/// Read an optional BED file as sorted, non-overlapping 1-based half-open regions
+/// `[start + 1, end + 1)`
+///
+/// BED intervals are 0-based half-open; shifting them here, once for every engine, gives the
+/// coordinates of alignment positions and of the `position` output column. Overlapping
+/// intervals (common in exome and panel BEDs) are merged so that no base is counted twice;
+/// the intervals as written stay available from `nanocov::parse_bed` for per-target reporting.
+/// Contig names are matched to the header (see [`ContigNames::resolve`]); intervals on contigs
+/// missing from the header are dropped with a warning.
+fn load_bed(path: Option<&std::path::PathBuf>, contigs: &ContigNames) -> Result<Option<BedRegions>, Box<dyn std::error::Error>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let (regions, missing) = contigs.rename_regions(nanocov::parse_bed(path)?);
+    warn_missing_contigs(path, &missing);
+    Ok(Some(normalize_bed(regions)))
+}
+
+/// Target regions from `--bed` and `--region` together, as for `load_bed`
+///
+/// Region strings are checked against the reference sequences of the header (under any of
+/// their names).
+fn load_targets(cli: &Cli, contigs: &ContigNames) -> Result<Option<BedRegions>, Box<dyn std::error::Error>> {
+    if cli.bed.is_none() && cli.regions.is_empty() {
+        return Ok(None);
+    }
+    let mut regions = match &cli.bed {
+        Some(path) => {
+            let (regions, missing) = contigs.rename_regions(nanocov::parse_bed(path)?);
+            warn_missing_contigs(path, &missing);
+            regions
+        }
+        None => HashMap::new(),
+    };
+
+    let contig_lengths = contigs.lengths_with_aliases();
+    for region in &cli.regions {
+        let (chrom, start, end) = nanocov::parse_region(region, &contig_lengths).map_err(|e| format!("Invalid --region: {}", e))?;
+        let chrom = contigs.resolve(&chrom).unwrap_or(&chrom).to_string();
+        regions.entry(chrom).or_default().push((start, end));
+    }
+
+    Ok(Some(normalize_bed(regions)))
+}
+
+/// Warn about BED contigs that match no reference sequence of the alignment header
+fn warn_missing_contigs(path: &std::path::Path, missing: &[String]) {
+    if missing.is_empty() {
+        return;
+    }
+    let mut listed = missing.iter().take(5).cloned().collect::<Vec<_>>().join(", ");
+    if missing.len() > 5 {
+        listed.push_str(", ...");
+    }
+    eprintln!(
+        "Warning: {} contig(s) in {:?} not found in the alignment header and skipped: {} (use --chrom-alias to match other names)",
+        missing.len(),
+        path,
+        listed
+    );
+}
+
+/// Shift 0-based BED intervals to 1-based half-open and merge overlapping ones
+fn normalize_bed(regions: BedRegions) -> BedRegions {
+    regions
+        .into_iter()
+        .map(|(chrom, intervals)| {
+            // Saturating: SAM references are shorter than 2^31, so nothing real ends at u32::MAX
+            let shifted: Vec<(u32, u32)> =
+                intervals.iter().map(|&(start, end)| (start.saturating_add(1), end.saturating_add(1))).collect();
+            (chrom, merge_regions(&shifted))
+        })
+        .collect()
+}
+
+/// Regions to count on one chromosome: BED regions, else chrom-BED ranges, else the whole
+/// chromosome, minus the `--exclude-bed` intervals
+///
+/// Regions are 1-based half-open (as returned by `load_bed`), clipped to the chromosome;
+/// empty regions are dropped.
+fn regions_for_chromosome(
+    chrom: &str,
+    chrom_length: u32,
+    bed_regions: &Option<HashMap<String, Vec<(u32, u32)>>>,
+    chrom_bed_regions: &Option<HashMap<String, Vec<(u32, u32)>>>,
+    exclude_regions: &Option<HashMap<String, Vec<(u32, u32)>>>,
+) -> Vec<(u32, u32)> {
+    let regions = if let Some(bed) = bed_regions {
+        bed.get(chrom).cloned().unwrap_or_default()
+    } else if let Some(chrom_bed) = chrom_bed_regions {
+        chrom_bed.get(chrom).cloned().unwrap_or_default()
+    } else {
+        // Process entire chromosome
+        vec![(1, chrom_length.saturating_add(1))]
+    };
+    let regions: Vec<(u32, u32)> = regions
+        .into_iter()
+        .map(|(start, end)| (start, end.min(chrom_length.saturating_add(1))))
+        .filter(|(start, end)| start < end)
+        .collect();
+
+    match exclude_regions.as_ref().and_then(|exclude| exclude.get(chrom)) {
+        Some(excluded) => subtract_regions(&regions, excluded),
+        None => regions,
+    }
+}
+
+/// Regions to count on every reference sequence, indexed by reference sequence ID, for the
+/// engines that read records in file order (sweep and per-group modes)
+struct ReferenceRegions {
+    names: Vec<String>,
+    regions: Vec<Vec<(u32, u32)>>,
+}
+
+impl ReferenceRegions {
+    /// Load the `--bed`/`--region`, `--chrom-bed` and `--exclude-bed` regions for the header
+    fn new(cli: &Cli, header: &noodles_sam::Header, contigs: &ContigNames) -> Result<Self, Box<dyn std::error::Error>> {
+        let bed_regions = load_targets(cli, contigs)?;
+        let chrom_bed_regions = load_bed(cli.chrom_bed.as_ref(), contigs)?;
+        let exclude_regions = load_bed(cli.exclude_bed.as_ref(), contigs)?;
+
+        let reference_sequences = header.reference_sequences();
+        let names: Vec<String> = reference_sequences.keys().map(|name| name.to_string()).collect();
+        let regions = reference_sequences
+            .iter()
+            .map(|(name, ref_seq)| {
+                regions_for_chromosome(
+                    &name.to_string(),
+                    ref_seq.length().get() as u32,
+                    &bed_regions,
+                    &chrom_bed_regions,
+                    &exclude_regions,
+                )
+            })
+            .collect();
+        Ok(Self { names, regions })
+    }
+
+    /// Count the blocks of a record on reference sequence `ref_id`, clipped to its regions
+    fn add_blocks(&self, events: &mut CoverageEvents, ref_id: usize, blocks: &[(u32, u32)]) {
+        for &(block_start, block_end) in blocks {
+            add_block_in_regions(events, &self.regions[ref_id], block_start, block_end);
+        }
+    }
+
+    /// Add the `events` counted on reference sequence `ref_id` to `coverage` and clear them
+    fn flush(&self, events: &mut CoverageEvents, ref_id: usize, coverage: &mut CoverageMap) {
+        if !events.is_empty() {
+            std::mem::take(events).add_into(coverage.entry(self.names[ref_id].clone()).or_default());
+        }
+    }
+}
+
+/// Reference sequence of the records read so far from one coordinate-sorted input
+#[derive(Debug, Default)]
+struct ReferenceOrder {
+    current: Option<usize>,
+}
+
+impl ReferenceOrder {
+    /// Move on to `ref_id`, the reference sequence of the next record of `input`
+    ///
+    /// Returns the reference sequence left behind, whose coverage is then complete. Fails
+    /// when `ref_id` comes before the current one, as the input is not coordinate-sorted.
+    fn advance(
+        &mut self,
+        ref_id: usize,
+        regions: &ReferenceRegions,
+        input: &AlignmentInput,
+    ) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+        match self.current {
+            Some(current) if ref_id < current => Err(format!(
+                "{:?} is not sorted by coordinate: {} follows {} (sort it with samtools sort)",
+                input.path, regions.names[ref_id], regions.names[current]
+            )
+            .into()),
+            Some(current) if ref_id == current => Ok(None),
+            previous => {
+                self.current = Some(ref_id);
+                Ok(previous)
+            }
+        }
+    }
+}
+
+/// Count the block `[block_start, block_end)` clipped to each of the sorted, non-overlapping
+/// `regions` it overlaps (as returned by `regions_for_chromosome`)
+///
+/// Overlapping regions are found by binary search, so a block costs O(log n) plus one event
+/// pair per overlapped region, even with hundreds of thousands of targets.
+fn add_block_in_regions(events: &mut CoverageEvents, regions: &[(u32, u32)], block_start: u32, block_end: u32) {
+    let first = regions.partition_point(|&(_, region_end)| region_end <= block_start);
+    for &(region_start, region_end) in &regions[first..] {
+        if region_start >= block_end {
+            break;
+        }
+        events.add(std::cmp::max(block_start, region_start), std::cmp::min(block_end, region_end));
+    }
+}
+
+/// Remove the `excluded` intervals from `regions` (both half-open `[start, end)`)
+fn subtract_regions(regions: &[(u32, u32)], excluded: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    let mut excluded = excluded.to_vec();
+    excluded.sort_unstable();
+
+    let mut remaining = Vec::new();
+    for &(start, end) in regions {
+        let mut current = start;
+        for &(excluded_start, excluded_end) in &excluded {
+            if excluded_start >= end {
+                break;
+            }
+            if excluded_end <= current {
+                continue;
+            }
+            if excluded_start > current {
+                remaining.push((current, excluded_start));
+            }
+            current = excluded_end;
+        }
+        if current < end {
+            remaining.push((current, end));
+        }
+    }
+    remaining
+}
+
+/// Sort intervals and merge overlapping or touching ones
+fn merge_regions(regions: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    let mut sorted = regions.to_vec();
+    sorted.sort_unstable();
+
+    let mut merged: Vec<(u32, u32)> = Vec::with_capacity(sorted.len());
+    for (start, end) in sorted {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = (*last_end).max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Genome size for mean coverage in read stats outputs, minus the bases removed by `--exclude-bed`
+///
+/// Taken from `--genome-size`, else summed over the chromosome lengths of `--genome` or of the
+/// alignment `header` (only the `--genome-contigs` if given).
+pub fn effective_genome_size(cli: &Cli, header: &noodles_sam::Header) -> Result<u64, Box<dyn std::error::Error>> {
+    let excluded = match &cli.exclude_bed {
+        Some(path) => nanocov::parse_bed(path)?,
+        None => HashMap::new(),
+    };
+
+    if let Some(genome_size) = cli.genome_size {
+        let excluded_bases: u64 = excluded
+            .values()
+            .flat_map(|intervals| merge_regions(intervals))
+            .map(|(start, end)| (end - start) as u64)
+            .sum();
+        return Ok(genome_size.saturating_sub(excluded_bases));
+    }
+
+    let (lengths, source) = match &cli.genome {
+        Some(path) => (nanocov::parse_genome_file(path)?, format!("{:?}", path)),
+        None => (
+            header
+                .reference_sequences()
+                .iter()
+                .map(|(name, ref_seq)| (name.to_string(), ref_seq.length().get() as u64))
+                .collect(),
+            "the alignment header".to_string(),
+        ),
+    };
+
+    // Match --genome-contigs and --exclude-bed names to the names of the lengths
+    let contigs = ContigNames::from_lengths(
+        cli,
+        lengths.iter().map(|(name, length)| (name.clone(), (*length).min(u32::MAX as u64) as u32)).collect(),
+    )?;
+    let selected = cli
+        .genome_contigs
+        .iter()
+        .map(|name| {
+            contigs
+                .resolve(name)
+                .map(str::to_string)
+                .ok_or_else(|| format!("Invalid --genome-contigs: contig '{}' not found in {}", name, source))
+        })
+        .collect::<Result<std::collections::HashSet<String>, _>>()?;
+    let (excluded, _) = contigs.rename_regions(excluded);
+
+    let mut genome_size = 0;
+    let mut counted_contigs = 0;
+    for (name, length) in &lengths {
+        if !selected.is_empty() && !selected.contains(name) {
+            continue;
+        }
+        let excluded_bases: u64 = excluded
+            .get(name)
+            .map(|intervals| {
+                merge_regions(intervals)
+                    .iter()
+                    .map(|&(start, end)| (end as u64).min(*length) - (start as u64).min(*length))
+                    .sum()
+            })
+            .unwrap_or(0);
+        genome_size += length - excluded_bases;
+        counted_contigs += 1;
+    }
+    println!("Genome size: {} bp over {} contigs from {}", genome_size, counted_contigs, source);
+
+    Ok(genome_size)
+}
+
+/// Process every chunk of every input in parallel
+///
+/// Headers and indexes are shared read-only; each rayon work split keeps one reusable reader
+/// per input. Returns the input index, coverage and read stats of each (input, chunk) pair,
+/// or why it failed; callers stop on the first failure rather than lose a chunk.
+fn process_chunks_parallel(
+    indexed_inputs: &[IndexedInput],
+    chunk_jobs: &[CoverageChunk],
+    filter: &ReadFilter,
+    rules: &CoverageRules,
+) -> Vec<Result<(usize, CoverageMap, ReadStatsCollector), String>> {
+    let jobs: Vec<(usize, &CoverageChunk)> = chunk_jobs
+        .iter()
+        .flat_map(|chunk| (0..indexed_inputs.len()).map(move |i| (i, chunk)))
+        .collect();
+
+    jobs.par_iter()
+        .map_init(
+            || ReaderCache::new(indexed_inputs.len()),
+            |readers, &(i, chunk)| {
+                let indexed = &indexed_inputs[i];
+                let mut stats = ReadStatsCollector::default();
+                // Errors become strings to cross threads; the caller stops on the first one
+                let coverage = readers
+                    .get(i, indexed.input)
+                    .and_then(|reader| process_chunk_efficiently(reader, indexed, chunk, filter, rules, &mut stats))
+                    .map_err(|e| {
+                        format!(
+                            "Failed to process {}:{}-{} of {:?}: {}",
+                            chunk.chromosome, chunk.start, chunk.end - 1, indexed.input.path, e
+                        )
+                    })?;
+                Ok((i, coverage, stats))
+            },
+        )
+        .collect()
+}
+
+/// Process a single chunk efficiently with optimized indexed access
 fn process_chunk_efficiently(
-    bam_path: &std::path::Path,
+    reader: &mut AlignmentReader,
+    indexed: &IndexedInput,
     chunk: &CoverageChunk,
+    filter: &ReadFilter,
+    rules: &CoverageRules,
+    stats: &mut ReadStatsCollector,
 ) -> Result<HashMap<String, HashMap<u32, u32>>, Box<dyn std::error::Error>> {
     use std::collections::HashMap;
-    use std::fs::File;
-    use std::io::BufReader;
-    use noodles_bam as bam;
     use noodles_core::Region;
     use noodles_core::Position;
 
-    // Open BAM reader for this thread
-    let mut reader = bam::io::Reader::new(BufReader::new(File::open(bam_path)?));
-    let mut bai_reader = noodles_bam::bai::Reader::new(File::open(&bam_path.with_extension("bam.bai"))?);
-    let index = bai_reader.read_index()?;
-    let header = reader.read_header()?;
+    let header = &indexed.header;
+    let index = &indexed.index;
 
     let mut coverage: HashMap<String, HashMap<u32, u32>> = HashMap::new();
+    let mut diff = DiffArray::new(chunk.start, chunk.end);
 
-    // Create region for this chunk
+    // Create region for this chunk (queries take the last position of the half-open chunk)
     let region = Region::new(
         chunk.chromosome.clone(),
-        Position::try_from(chunk.start as usize)?..=Position::try_from(chunk.end as usize)?,
+        Position::try_from(chunk.start as usize)?..=Position::try_from((chunk.end - 1) as usize)?,
     );
 
-    // Query BAM for this region
-    let query = reader.query(&header, &index, &region)?;
-    
+    // Query the alignment file for this region
+    let query = reader.query(header, index, &region)?;
+    let mut mates = MateOverlaps::default();
+
     for result in query {
         let record = result?;
-        if record.flags().is_unmapped() {
-            continue;
-        }
 
         let start_pos = match record.alignment_start() {
             Some(Ok(pos)) => pos.get() as u32,
             _ => continue,
         };
 
-        let alignment_len = calculate_reference_span(&record.cigar());
-        let end_pos = start_pos + alignment_len;
-
-        // Skip if alignment doesn't overlap with our chunk
-        if end_pos < chunk.start || start_pos > chunk.end {
+        let passed = match chunk.stats_starts {
+            Some((first, last)) if start_pos >= first && start_pos < last => {
+                stats.add_record_with_filter(record.as_ref(), filter)? && filter.passes_flags(record.as_ref())?
+            }
+            _ => filter.passes(record.as_ref())?,
+        };
+        if !passed {
             continue;
         }
 
-        // Calculate overlap with chunk boundaries
-        let overlap_start = std::cmp::max(start_pos, chunk.start);
-        let overlap_end = std::cmp::min(end_pos, chunk.end);
-
-        // Update coverage efficiently using range iteration
-        let chrom_coverage = coverage.entry(chunk.chromosome.clone()).or_default();
-        
-        // Optimized coverage update - avoid per-base loop for long alignments
-        if alignment_len <= 1000 {
-            // For short alignments, use per-base counting
-            for pos in overlap_start..overlap_end {
-                *chrom_coverage.entry(pos).or_insert(0) += 1;
-            }
-        } else {
-            // For long alignments, use a more efficient approach
-            update_coverage_range(chrom_coverage, overlap_start, overlap_end);
+        // Two updates per aligned block, clipped to the chunk boundaries
+        for (block_start, block_end) in rules.counted_blocks(header, record.as_ref(), &mut mates)? {
+            diff.add(block_start, block_end);
         }
     }
 
-    Ok(coverage)
-}
-
-/// Efficiently update coverage for a range (optimized for long reads)
-fn update_coverage_range(coverage: &mut HashMap<u32, u32>, start: u32, end: u32) {
-    // For very long ranges, we could implement a more sophisticated
-    // interval-based approach, but for now use the simple approach
-    for pos in start..end {
-        *coverage.entry(pos).or_insert(0) += 1;
+    // Expand the difference array into per-base depths for the writers and plots
+    let mut positions = HashMap::new();
+    diff.add_into(&mut positions);
+    if !positions.is_empty() {
+        coverage.insert(chunk.chromosome.clone(), positions);
     }
+
+    Ok(coverage)
 }
 
 /// Write coverage data efficiently using parallel formatting
 fn write_coverage_parallel(
     coverage: &HashMap<String, HashMap<u32, u32>>,
+    header_lines: &[String],
     output_path: &std::path::Path,
 ) -> Result<(), Box<dyn std::error::Error>> {
     use std::io::{Write, BufWriter};
@@ -888,6 +1388,9 @@ fn write_coverage_parallel(
     use std::fmt::Write as FmtWrite;
 
     let mut out = BufWriter::new(File::create(output_path)?);
+    for line in header_lines {
+        writeln!(out, "{}", line)?;
+    }
     writeln!(out, "#chromosome\tposition\tcount")?;
 
     // Parallelize formatting of coverage lines per chromosome
@@ -919,7 +1422,10 @@ fn write_coverage_parallel(
 /// Generate plots from the merged coverage map
 fn generate_plots_from_coverage_map(
     cli: &Cli,
+    contigs: &ContigNames,
     coverage: &HashMap<String, HashMap<u32, u32>>,
+    output: &std::path::Path,
+    label: Option<&str>,
     read_stats: Option<&ReadStats>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     use std::collections::HashMap;
@@ -928,8 +1434,8 @@ fn generate_plots_from_coverage_map(
         return Ok(());
     }
 
-    let output_stem = cli.output.file_stem().unwrap_or_default().to_string_lossy();
-    let output_dir = cli.output.parent().unwrap_or_else(|| std::path::Path::new("."));
+    let output_stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let output_dir = output.parent().unwrap_or_else(|| std::path::Path::new("."));
 
     // Apply theme if specified
     if let Some(theme) = &cli.theme {
@@ -938,14 +1444,18 @@ fn generate_plots_from_coverage_map(
 
     // Generate individual chromosome plots
     for (chrom, chrom_coverage) in coverage {
-        let plot_path = output_dir.join(format!("coverage.{}.png", chrom));
+        let plot_path = output_dir.join(format!("{}.{}.png", output_stem, chrom));
+        let title = match label {
+            Some(label) => format!("{} ({})", chrom, label),
+            None => chrom.clone(),
+        };
         
         // Determine plot range from coverage data
         let min_pos = chrom_coverage.keys().min().copied().unwrap_or(0);
         let max_pos = chrom_coverage.keys().max().copied().unwrap_or(0);
         
         crate::plotting::plot_per_base_coverage_with_range(
-            chrom,
+            &title,
             chrom_coverage,
             plot_path.to_str().unwrap(),
             min_pos,
@@ -960,10 +1470,10 @@ fn generate_plots_from_coverage_map(
     if coverage.len() > 1 && !cli.skip_multi_plot {
         let multi_plot_path = output_dir.join(format!("{}.multi_chrom.png", output_stem));
         
-        // Convert to the expected format for plot_all_chromosomes
+        // Convert to the expected format for plot_all_chromosomes, labelled with chr-style names
         let chrom_coverages: HashMap<String, &HashMap<u32, u32>> = coverage
             .iter()
-            .map(|(k, v)| (k.clone(), v))
+            .map(|(k, v)| (contigs.display_name(k), v))
             .collect();
 
         // Get current theme
@@ -980,3 +1490,197 @@ fn generate_plots_from_coverage_map(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::path::Path;
+
+    /// Indexed BAM with a 100 bp chr1 and two reads, over positions 1-10 and 91-100
+    fn write_indexed_bam(path: &Path) {
+        use noodles_bam as bam;
+        use noodles_sam as sam;
+        use sam::alignment::io::Write as _;
+        use sam::alignment::record::cigar::{op::Kind, Op};
+        use sam::alignment::record::Flags;
+        use sam::alignment::record_buf::Cigar;
+
+        // Indexing needs a coordinate-sorted header
+        let header: sam::Header = "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:100\n".parse().unwrap();
+        let mut writer = bam::io::Writer::new(std::fs::File::create(path).unwrap());
+        writer.write_header(&header).unwrap();
+        for (i, start) in [1, 91].into_iter().enumerate() {
+            let record = sam::alignment::RecordBuf::builder()
+                .set_name(format!("read{}", i))
+                .set_flags(Flags::empty())
+                .set_reference_sequence_id(0)
+                .set_alignment_start(noodles_core::Position::new(start).unwrap())
+                .set_cigar(Cigar::from(vec![Op::new(Kind::Match, 10)]))
+                .build();
+            writer.write_alignment_record(&header, &record).unwrap();
+        }
+        writer.try_finish().unwrap();
+
+        let index = bam::fs::index(path).unwrap();
+        bam::bai::fs::write(path.with_extension("bam.bai"), &index).unwrap();
+    }
+
+    /// Run the engine selected by `args` and return the (position, depth) rows of the output
+    fn coverage_rows(bam: &Path, args: &[&str]) -> Vec<(u32, u32)> {
+        let output = bam.with_file_name(format!("coverage{}.tsv", args.join("").replace(['-', '/', '.'], "")));
+        let mut argv = vec!["nanocov", "-i", bam.to_str().unwrap(), "-o", output.to_str().unwrap(), "--no-plots"];
+        argv.extend_from_slice(args);
+        let cli = Cli::parse_from(argv);
+
+        let inputs = vec![AlignmentInput::new(bam, None, None).unwrap()];
+        run_coverage(&cli, &inputs, &ReadFilter::from_cli(&cli)).unwrap();
+
+        let text = std::fs::read_to_string(&output).unwrap();
+        assert!(text.starts_with(COORDINATES_HEADER));
+        let mut rows: Vec<(u32, u32)> = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (fields[1].parse().unwrap(), fields[2].parse().unwrap())
+            })
+            .collect();
+        rows.sort_unstable();
+        rows
+    }
+
+    #[test]
+    fn test_bed_boundaries_are_the_same_in_every_engine() {
+        let dir = tempfile::tempdir().unwrap();
+        let bam = dir.path().join("sample.bam");
+        write_indexed_bam(&bam);
+        // First base, positions 10-11 (only 10 is covered) and the last base of chr1
+        let bed = dir.path().join("regions.bed");
+        std::fs::write(&bed, "chr1\t0\t1\nchr1\t9\t11\nchr1\t99\t100\n").unwrap();
+        let bed = bed.to_str().unwrap();
+        let overlapping = dir.path().join("overlapping.bed");
+        std::fs::write(&overlapping, "chr1\t4\t9\nchr1\t2\t6\nchr1\t5\t20\n").unwrap();
+        let overlapping = overlapping.to_str().unwrap();
+
+        let whole: Vec<(u32, u32)> = (1..=10).chain(91..=100).map(|pos| (pos, 1)).collect();
+        for engine in [&[][..], &["--streaming"], &["--sweep"], &["-c", "7"]] {
+            assert_eq!(coverage_rows(&bam, engine), whole, "whole chromosome, {:?}", engine);
+
+            let with_bed = [engine, &["-b", bed]].concat();
+            assert_eq!(coverage_rows(&bam, &with_bed), vec![(1, 1), (10, 1), (100, 1)], "{:?}", with_bed);
+
+            // Overlapping targets count each base once
+            let with_overlaps = [engine, &["-b", overlapping]].concat();
+            let once: Vec<(u32, u32)> = (3..=10).map(|pos| (pos, 1)).collect();
+            assert_eq!(coverage_rows(&bam, &with_overlaps), once, "{:?}", with_overlaps);
+        }
+    }
+
+    #[test]
+    fn test_subtract_regions() {
+        let regions = [(1, 100), (200, 300)];
+        // Unsorted and overlapping exclusions, one spanning the gap between regions
+        let excluded = [(250, 260), (10, 20), (15, 30), (90, 210)];
+        assert_eq!(subtract_regions(&regions, &excluded), vec![(1, 10), (30, 90), (210, 250), (260, 300)]);
+        assert_eq!(subtract_regions(&regions, &[(0, 1000)]), vec![]);
+        assert_eq!(subtract_regions(&regions, &[]), regions.to_vec());
+    }
+
+    #[test]
+    fn test_load_bed_shifts_and_merges_intervals() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("targets.bed");
+        std::fs::write(&path, "chr1\t100\t200\nchr1\t0\t10\nchr1\t150\t300\nchr2\t5\t6\n").unwrap();
+
+        let header = "@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:1000\n".parse().unwrap();
+        let contigs = ContigNames::new(&Cli::parse_from(["nanocov", "-i", "in.bam"]), &header).unwrap();
+        let regions = load_bed(Some(&path), &contigs).unwrap().unwrap();
+        assert_eq!(regions["chr1"], vec![(1, 11), (101, 301)]);
+        assert_eq!(regions["chr2"], vec![(6, 7)]);
+        assert!(load_bed(None, &contigs).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_regions_at_u32_max_do_not_overflow() {
+        let bed = HashMap::from([("chr1".to_string(), vec![(10, u32::MAX), (u32::MAX - 1, u32::MAX)])]);
+        assert_eq!(normalize_bed(bed)["chr1"], vec![(11, u32::MAX)]);
+        assert_eq!(regions_for_chromosome("chr1", u32::MAX, &None, &None, &None), vec![(1, u32::MAX)]);
+    }
+
+    #[test]
+    fn test_effective_genome_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let exclude = dir.path().join("exclude.bed");
+        // The chr2 interval runs past the end of the contig and only counts up to it
+        std::fs::write(&exclude, "1\t0\t100\nchr1\t50\t150\nchr2\t900\t2000\n").unwrap();
+        let exclude = exclude.to_str().unwrap();
+        let header: noodles_sam::Header = "@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:1000\n@SQ\tSN:chrUn_1\tLN:500\n"
+            .parse()
+            .unwrap();
+        let genome_size = |args: &[&str]| {
+            let cli = Cli::parse_from(["nanocov", "-i", "in.bam"].iter().chain(args));
+            effective_genome_size(&cli, &header).unwrap()
+        };
+
+        assert_eq!(genome_size(&[]), 2500);
+        assert_eq!(genome_size(&["--genome-contigs", "chr1,2"]), 2000);
+        assert_eq!(genome_size(&["--genome-contigs", "chr1,chr2", "--exclude-bed", exclude]), 2000 - 150 - 100);
+        // A given genome size has no contigs to match: every excluded interval counts as written
+        assert_eq!(genome_size(&["--genome-size", "3000", "--exclude-bed", exclude]), 3000 - 1300);
+
+        let genome = dir.path().join("genome.txt");
+        std::fs::write(&genome, "chr1\t2000\n").unwrap();
+        assert_eq!(genome_size(&["--genome", genome.to_str().unwrap()]), 2000);
+
+        let cli = Cli::parse_from(["nanocov", "-i", "in.bam", "--genome-contigs", "chr3"]);
+        assert!(effective_genome_size(&cli, &header).is_err());
+    }
+
+    #[test]
+    fn test_add_block_in_regions() {
+        let regions = [(1, 5), (10, 12), (20, 30), (40, 50)];
+        let mut events = CoverageEvents::default();
+        // Spans the end of one region, a whole region and the start of another
+        add_block_in_regions(&mut events, &regions, 4, 22);
+        // Between regions, and past the last one
+        add_block_in_regions(&mut events, &regions, 6, 9);
+        add_block_in_regions(&mut events, &regions, 50, 60);
+
+        let mut coverage = HashMap::new();
+        events.add_into(&mut coverage);
+        let mut positions: Vec<u32> = coverage.keys().copied().collect();
+        positions.sort_unstable();
+        assert_eq!(positions, vec![4, 10, 11, 20, 21]);
+    }
+
+    #[test]
+    fn test_sweep_flushes_each_reference_in_order() {
+        let header = "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:100\n@SQ\tSN:chr2\tLN:100\n".parse().unwrap();
+        let cli = Cli::parse_from(["nanocov", "-i", "in.bam"]);
+        let contigs = ContigNames::new(&cli, &header).unwrap();
+        let regions = ReferenceRegions::new(&cli, &header, &contigs).unwrap();
+        let input = AlignmentInput::new(Path::new("in.bam"), None, None).unwrap();
+
+        let mut coverage = CoverageMap::new();
+        let mut events = CoverageEvents::default();
+        let mut order = ReferenceOrder::default();
+        assert_eq!(order.advance(0, &regions, &input).unwrap(), None);
+        regions.add_blocks(&mut events, 0, &[(1, 3)]);
+        assert_eq!(order.advance(0, &regions, &input).unwrap(), None);
+        regions.add_blocks(&mut events, 0, &[(2, 4)]);
+        // Moving to chr2 completes chr1; only chr2 events are kept from here on
+        let done = order.advance(1, &regions, &input).unwrap().unwrap();
+        regions.flush(&mut events, done, &mut coverage);
+        assert!(events.is_empty());
+        assert_eq!(coverage["chr1"], HashMap::from([(1, 1), (2, 2), (3, 1)]));
+
+        let error = order.advance(0, &regions, &input).unwrap_err().to_string();
+        assert!(error.contains("not sorted by coordinate: chr1 follows chr2"), "{}", error);
+    }
+
+    #[test]
+    fn test_merge_regions() {
+        assert_eq!(merge_regions(&[(50, 60), (1, 10), (5, 20), (20, 30)]), vec![(1, 30), (50, 60)]);
+    }
+}
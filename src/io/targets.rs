@@ -0,0 +1,222 @@
+// src/io/targets.rs
+// Per-target summary for nanocov: one row of depth statistics per BED interval
+
+use super::contigs::ContigNames;
+use super::{normalize_bed, subtract_regions};
+use crate::cli::Cli;
+use nanocov::BedRecord;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Depth thresholds reported as percent of target bases
+const DEPTH_THRESHOLDS: [u32; 4] = [1, 10, 20, 30];
+
+/// Depth statistics over the bases of one target
+#[derive(Debug, PartialEq)]
+struct TargetSummary {
+    length: u64,
+    mean: f64,
+    median: f64,
+    min: u32,
+    max: u32,
+    pct_at_least: [f64; 4],
+    zero_bases: u64,
+}
+
+impl TargetSummary {
+    /// Summarize the depth of the 1-based half-open `regions`; positions missing from
+    /// `coverage` have depth 0
+    fn from_coverage(coverage: Option<&HashMap<u32, u32>>, regions: &[(u32, u32)]) -> Self {
+        // Histogram of depths, so large targets need no per-base buffer
+        let mut histogram: BTreeMap<u32, u64> = BTreeMap::new();
+        for &(start, end) in regions {
+            for pos in start..end {
+                let depth = coverage.and_then(|positions| positions.get(&pos)).copied().unwrap_or(0);
+                *histogram.entry(depth).or_insert(0) += 1;
+            }
+        }
+
+        let length: u64 = histogram.values().sum();
+        let total: u64 = histogram.iter().map(|(&depth, &count)| depth as u64 * count).sum();
+        let ratio = |numerator: f64| if length > 0 { numerator / length as f64 } else { 0.0 };
+        let pct_at_least = DEPTH_THRESHOLDS.map(|threshold| {
+            let bases: u64 = histogram.range(threshold..).map(|(_, &count)| count).sum();
+            ratio(bases as f64 * 100.0)
+        });
+
+        Self {
+            length,
+            mean: ratio(total as f64),
+            median: median(&histogram, length),
+            min: histogram.keys().next().copied().unwrap_or(0),
+            max: histogram.keys().next_back().copied().unwrap_or(0),
+            pct_at_least,
+            zero_bases: histogram.get(&0).copied().unwrap_or(0),
+        }
+    }
+}
+
+/// Median of `length` depths given as a histogram (mean of the two middle values for an even count)
+fn median(histogram: &BTreeMap<u32, u64>, length: u64) -> f64 {
+    if length == 0 {
+        return 0.0;
+    }
+    // Depth at 0-based rank `rank` of the sorted depths
+    let depth_at = |rank: u64| {
+        let mut seen = 0;
+        for (&depth, &count) in histogram {
+            seen += count;
+            if seen > rank {
+                return depth;
+            }
+        }
+        0
+    };
+    if length % 2 == 1 {
+        depth_at(length / 2) as f64
+    } else {
+        (depth_at(length / 2 - 1) as f64 + depth_at(length / 2) as f64) / 2.0
+    }
+}
+
+/// Per-target summary next to a coverage output (`coverage.tsv` -> `coverage.targets.tsv`)
+pub fn target_summary_path(output: &Path) -> PathBuf {
+    output.with_extension("targets.tsv")
+}
+
+/// Write the per-target summary for the `--bed` intervals, if any, next to `output`
+///
+/// Targets are reported as written, in file order, overlapping ones included; bases removed
+/// by `--exclude-bed` are left out of their statistics. Contig names are matched to the
+/// coverage (header) names through `contigs`.
+pub fn write_target_summary_for(
+    cli: &Cli,
+    contigs: &ContigNames,
+    coverage: &HashMap<String, HashMap<u32, u32>>,
+    output: &Path,
+    header_lines: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(bed) = &cli.bed else {
+        return Ok(());
+    };
+    let targets = nanocov::parse_bed_records(bed)?;
+    // Missing contigs were already reported when the regions were loaded
+    let exclude_regions = match &cli.exclude_bed {
+        Some(path) => Some(normalize_bed(contigs.rename_regions(nanocov::parse_bed(path)?).0)),
+        None => None,
+    };
+
+    let path = target_summary_path(output);
+    write_target_summary(&path, &targets, contigs, coverage, exclude_regions.as_ref(), header_lines)?;
+    println!("Wrote per-target summary of {} targets to {:?}", targets.len(), path);
+    Ok(())
+}
+
+/// Write one row of depth statistics per target
+fn write_target_summary(
+    path: &Path,
+    targets: &[BedRecord],
+    contigs: &ContigNames,
+    coverage: &HashMap<String, HashMap<u32, u32>>,
+    exclude_regions: Option<&HashMap<String, Vec<(u32, u32)>>>,
+    header_lines: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for line in header_lines {
+        writeln!(out, "{}", line)?;
+    }
+    writeln!(
+        out,
+        "#chromosome\tstart\tend\tname\tscore\tstrand\tlength\tmean_depth\tmedian_depth\tmin_depth\tmax_depth\tpct_1x\tpct_10x\tpct_20x\tpct_30x\tzero_bases"
+    )?;
+
+    for target in targets {
+        // BED intervals are 0-based half-open; coverage positions are 1-based
+        let chrom = contigs.resolve(&target.chrom).unwrap_or(&target.chrom);
+        let mut regions = vec![(target.start.saturating_add(1), target.end.saturating_add(1))];
+        if let Some(excluded) = exclude_regions.and_then(|exclude| exclude.get(chrom)) {
+            regions = subtract_regions(&regions, excluded);
+        }
+        let summary = TargetSummary::from_coverage(coverage.get(chrom), &regions);
+
+        let column = |value: &Option<String>| value.clone().unwrap_or_else(|| ".".to_string());
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.2}\t{:.1}\t{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{:.2}\t{}",
+            target.chrom,
+            target.start,
+            target.end,
+            column(&target.name),
+            column(&target.score),
+            column(&target.strand),
+            summary.length,
+            summary.mean,
+            summary.median,
+            summary.min,
+            summary.max,
+            summary.pct_at_least[0],
+            summary.pct_at_least[1],
+            summary.pct_at_least[2],
+            summary.pct_at_least[3],
+            summary.zero_bases,
+        )?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_summary_counts_uncovered_bases() {
+        // Positions 1-6 with depths 0, 10, 20, 30, 0, 40
+        let coverage: HashMap<u32, u32> = [(2, 10), (3, 20), (4, 30), (6, 40)].into_iter().collect();
+        let summary = TargetSummary::from_coverage(Some(&coverage), &[(1, 7)]);
+        assert_eq!(
+            summary,
+            TargetSummary {
+                length: 6,
+                mean: 100.0 / 6.0,
+                median: 15.0,
+                min: 0,
+                max: 40,
+                pct_at_least: [400.0 / 6.0, 400.0 / 6.0, 300.0 / 6.0, 200.0 / 6.0],
+                zero_bases: 2,
+            }
+        );
+
+        let uncovered = TargetSummary::from_coverage(None, &[(1, 4)]);
+        assert_eq!((uncovered.length, uncovered.median, uncovered.max, uncovered.zero_bases), (3, 0.0, 0, 3));
+    }
+
+    #[test]
+    fn test_write_target_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("coverage.targets.tsv");
+        let target = |start, end, name: Option<&str>| BedRecord {
+            chrom: "chr1".to_string(),
+            start,
+            end,
+            name: name.map(str::to_string),
+            score: None,
+            strand: name.map(|_| "+".to_string()),
+        };
+        // BED 0-4 is positions 1-4; the second target overlaps the first and loses position 3 to the exclusion
+        let targets = vec![target(0, 4, Some("amp1")), target(2, 5, None)];
+        let coverage = HashMap::from([("chr1".to_string(), HashMap::from([(1, 5), (2, 5), (3, 7), (4, 9)]))]);
+        let exclude = HashMap::from([("chr1".to_string(), vec![(3, 4)])]);
+
+        write_target_summary(&path, &targets, &ContigNames::default(), &coverage, Some(&exclude), &[]).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let rows: Vec<&str> = content.lines().skip(1).collect();
+        assert_eq!(rows[0], "chr1\t0\t4\tamp1\t.\t+\t3\t6.33\t5.0\t5\t9\t100.00\t0.00\t0.00\t0.00\t0");
+        assert_eq!(rows[1], "chr1\t2\t5\t.\t.\t.\t2\t4.50\t4.5\t0\t9\t50.00\t0.00\t0.00\t0.00\t1");
+        assert_eq!(target_summary_path(Path::new("out/coverage.tsv")), PathBuf::from("out/coverage.targets.tsv"));
+    }
+}
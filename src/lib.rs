@@ -2,23 +2,133 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::io::BufRead;
 
-pub fn parse_bed(path: &PathBuf) -> Result<HashMap<String, Vec<(u32, u32)>>, Box<dyn std::error::Error>> {
-    let mut regions: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
+/// One interval of a BED file with its optional name, score and strand columns
+#[derive(Debug, Clone, PartialEq)]
+pub struct BedRecord {
+    pub chrom: String,
+    /// 0-based start, as written
+    pub start: u32,
+    /// Exclusive end, as written
+    pub end: u32,
+    pub name: Option<String>,
+    pub score: Option<String>,
+    pub strand: Option<String>,
+}
+
+/// Read the intervals of a BED file in file order, as written: 0-based half-open
+/// (`chr1 0 10` is the first ten bases)
+///
+/// Columns after the sixth (thickStart, blocks, ...) are ignored.
+pub fn parse_bed_records(path: &PathBuf) -> Result<Vec<BedRecord>, Box<dyn std::error::Error>> {
+    let mut records = Vec::new();
     let file = std::fs::File::open(path)?;
     let reader = std::io::BufReader::new(file);
     for line in reader.lines() {
         let line = line?;
-        if line.starts_with('#') || line.trim().is_empty() {
+        if line.starts_with('#') || is_bed_header_line(&line) || line.trim().is_empty() {
             continue;
         }
         let fields: Vec<_> = line.split_whitespace().collect();
         if fields.len() < 3 {
             continue;
         }
-        let chrom = fields[0].to_string();
         let start: u32 = fields[1].parse()?;
         let end: u32 = fields[2].parse()?;
-        regions.entry(chrom).or_default().push((start, end));
+        if end < start {
+            return Err(format!("Invalid BED interval in {:?}: end {} is before start {} ({})", path, end, start, line).into());
+        }
+        let column = |i: usize| fields.get(i).map(|s| s.to_string());
+        records.push(BedRecord {
+            chrom: fields[0].to_string(),
+            start,
+            end,
+            name: column(3),
+            score: column(4),
+            strand: column(5),
+        });
+    }
+    Ok(records)
+}
+
+/// Whether a BED line is a `track` or `browser` line: the keyword as a whole word, so that
+/// contigs such as `tracker_1` are still read
+fn is_bed_header_line(line: &str) -> bool {
+    matches!(line.split_whitespace().next(), Some("track" | "browser"))
+}
+
+/// Read the regions of a BED file by chromosome, as written: 0-based half-open
+/// (`chr1 0 10` is the first ten bases)
+pub fn parse_bed(path: &PathBuf) -> Result<HashMap<String, Vec<(u32, u32)>>, Box<dyn std::error::Error>> {
+    let mut regions: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
+    for record in parse_bed_records(path)? {
+        regions.entry(record.chrom).or_default().push((record.start, record.end));
     }
     Ok(regions)
 }
+
+/// Read chromosome lengths from a samtools `.fai` index or a two-column genome file
+/// (`chrom<TAB>length`, as used by bedtools), in file order
+///
+/// Only the first two columns are used, so both formats are read the same way.
+pub fn parse_genome_file(path: &PathBuf) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+    let mut lengths = Vec::new();
+    let file = std::fs::File::open(path)?;
+    let reader = std::io::BufReader::new(file);
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.len() < 2 {
+            return Err(format!("Invalid line in genome file {:?} (expected chromosome and length): {}", path, line).into());
+        }
+        let length: u64 = fields[1]
+            .parse()
+            .map_err(|_| format!("Invalid length '{}' for {} in genome file {:?}", fields[1], fields[0], path))?;
+        lengths.push((fields[0].to_string(), length));
+    }
+    Ok(lengths)
+}
+
+/// Parse a samtools-style region string (`chr1`, `chr1:1,000,000-2,000,000`, `chr1:500-` or
+/// `chr1:500`) into a 0-based half-open interval, like a `parse_bed` entry
+///
+/// Coordinates in the string are 1-based and inclusive; a missing end means the end of the
+/// contig. `contig_lengths` holds the reference sequences of the alignment header, so unknown
+/// contigs and out-of-range coordinates are rejected. A name that is itself a contig (even
+/// with a colon, such as HLA alleles) is taken as the whole contig.
+pub fn parse_region(region: &str, contig_lengths: &HashMap<String, u32>) -> Result<(String, u32, u32), String> {
+    let region = region.trim();
+    if let Some(&length) = contig_lengths.get(region) {
+        return Ok((region.to_string(), 0, length));
+    }
+
+    let (chrom, range) = region
+        .rsplit_once(':')
+        .ok_or_else(|| format!("unknown contig '{}' in region '{}' (not in the alignment header)", region, region))?;
+    let length = *contig_lengths
+        .get(chrom)
+        .ok_or_else(|| format!("unknown contig '{}' in region '{}' (not in the alignment header)", chrom, region))?;
+
+    let parse_position = |s: &str| -> Result<u32, String> {
+        let digits: String = s.chars().filter(|&c| c != ',' && c != '_').collect();
+        digits.trim().parse::<u32>().map_err(|_| format!("invalid position '{}' in region '{}'", s, region))
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, "")) => (parse_position(start)?, length),
+        Some((start, end)) => (parse_position(start)?, parse_position(end)?),
+        None => (parse_position(range)?, length),
+    };
+
+    if start == 0 {
+        return Err(format!("region '{}' starts at 0; region positions are 1-based", region));
+    }
+    if start > end {
+        return Err(format!("region '{}' ends before it starts", region));
+    }
+    if end > length {
+        return Err(format!("region '{}' ends at {}, past the end of {} (length {})", region, end, chrom, length));
+    }
+    Ok((chrom.to_string(), start - 1, end))
+}
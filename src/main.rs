@@ -4,6 +4,8 @@ mod utils;
 mod plotting;
 
 use crate::cli::Cli;
+use crate::io::alignment::{self, AlignmentFormat, AlignmentInput};
+use crate::io::filter::ReadFilter;
 use std::path::PathBuf;
 
 use clap::Parser;
@@ -11,24 +13,53 @@ use clap::Parser;
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
-    // Prepare BAM and BAI file paths
-    let bam_path = &cli.input;
-    let bai_path = bam_path.with_extension("bam.bai");
-    if !bai_path.exists() {
-        eprintln!("BAM index not found at {:?}. Please run 'samtools index {:?}' to create it.", bai_path, bam_path);
-        std::process::exit(1);
+    if !cli.index.is_empty() && cli.index.len() != cli.input.len() {
+        return Err(format!("Got {} index files for {} inputs; give one --index per input", cli.index.len(), cli.input.len()).into());
     }
 
-    // Extract read stats
-    let read_stats = crate::utils::extract_read_stats(bam_path)?;
+    // BGZF decompression workers for whole-file passes (default: half of available cores)
+    let threads = cli.threads.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, |n| (n.get() / 2).max(1))
+    });
+
+    // Prepare alignment inputs (BAM, or CRAM with a reference) and check for their indexes
+    let mut inputs = Vec::with_capacity(cli.input.len());
+    for (i, bam_path) in cli.input.iter().enumerate() {
+        let input = AlignmentInput::new(bam_path, cli.reference.as_deref(), cli.index.get(i).map(PathBuf::as_path))?
+            .with_decompression_threads(threads);
+        if input.index_path().is_none() && !input.is_stdin() && !cli.sweep {
+            let kind = match input.format {
+                AlignmentFormat::Bam => "BAM",
+                AlignmentFormat::Cram => "CRAM",
+            };
+            eprintln!("{} index not found (looked for {:?}); reading the whole file in a single pass.", kind, input.index_candidates());
+            eprintln!("Run 'samtools index {:?}' to enable indexed processing.", bam_path);
+        }
+        inputs.push(input);
+    }
+
+    // Merged inputs must be aligned to the same reference
+    alignment::check_reference_dictionaries(&inputs)?;
+    if inputs.len() > 1 && !cli.multi_sample {
+        println!("Merging coverage from {} input files", inputs.len());
+    }
+
+    // Coverage engines gather read stats while reading (or in one shared pass)
+    let filter = ReadFilter::from_cli(&cli);
+    let (read_stats, header) = io::run_coverage(&cli, &inputs, &filter)?;
+    for (label, count) in filter.removed_counts(&read_stats.filtered) {
+        println!("{}: {} removed", label, count);
+    }
 
     // Generate cramino output if requested
     if cli.cramino_output {
         let cramino_path = if let Some(p) = &cli.cramino_output_path {
             p.clone()
+        } else if inputs[0].is_stdin() {
+            PathBuf::from("stdin.cramino")
         } else {
-            // Default: use input filename with .cramino extension
-            let mut default_path = PathBuf::from(bam_path);
+            // Default: use (first) input filename with .cramino extension
+            let mut default_path = inputs[0].path.clone();
             default_path.set_extension("cramino");
             default_path
         };
@@ -36,17 +67,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("Generating cramino-like output at {:?}", cramino_path);
         
         // We don't have coverage info yet, so use 0.0 for now
-        // Use genome_size from CLI if provided
+        // Mean coverage over the genome size, minus the excluded regions
+        let genome_size = io::effective_genome_size(&cli, &header)?;
         io::cramino::generate_cramino_output(
-            bam_path,
+            &inputs,
             &cramino_path,
             Some(&read_stats),
+            &filter,
             0.0, // We don't have coverage info yet
-            cli.genome_size.unwrap_or(0),
+            genome_size,
         )?;
     }
 
-    // Move coverage calculation and output logic to io module, pass read_stats
-    io::run_coverage(&cli, Some(read_stats))?;
     Ok(())
 }
@@ -7,6 +7,16 @@ use crate::plotting::themes::ColorTheme;
 use plotters::prelude::*;
 use std::collections::HashMap;
 
+/// Chromosome name without a `chr`, `Chr` or `CHR` prefix
+///
+/// Names from other schemes (e.g. RefSeq accessions) are mapped to `chr` names by the caller.
+fn strip_chr_prefix(chrom: &str) -> &str {
+    ["chr", "Chr", "CHR"]
+        .iter()
+        .find_map(|prefix| chrom.strip_prefix(prefix))
+        .unwrap_or(chrom)
+}
+
 /// Plot all chromosomes on a single chart for comparison
 ///
 /// Creates a continuous bar chart showing 100kb-binned coverage across all chromosomes,
@@ -42,7 +52,7 @@ pub fn plot_all_chromosomes(
     
     for (chrom, coverage) in chrom_coverages {
         // Skip non-canonical chromosomes or empty data
-        let chrom_base = chrom.trim_start_matches("chr");
+        let chrom_base = strip_chr_prefix(chrom);
         if !canonical_chroms.contains(&chrom.as_str()) && !canonical_chroms.contains(&chrom_base) {
             continue;
         }
@@ -52,11 +62,7 @@ pub fn plot_all_chromosomes(
         }
         
         // Format chromosome name consistently for display
-        let display_name = if chrom.starts_with("chr") {
-            chrom.trim_start_matches("chr").to_string()
-        } else {
-            chrom.to_string()
-        };
+        let display_name = chrom_base.to_string();
         
         // Bin the coverage data
         let mut binned: std::collections::BTreeMap<u32, (u64, u32)> = std::collections::BTreeMap::new();
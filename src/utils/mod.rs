@@ -1,11 +1,12 @@
 // src/utils/mod.rs
 
-use noodles_bam as bam;
-use std::fs::File;
-use std::io::BufReader;
+use crate::io::alignment::AlignmentInput;
+use crate::io::filter::{FilterCounts, ReadFilter};
+use noodles_sam as sam;
 
 pub struct ReadStats {
     pub n50: u32,
+    pub n75: u32,
     pub mean_len: f64,
     pub median_len: f64,
     pub mean_qual: f64,
@@ -13,85 +14,240 @@ pub struct ReadStats {
     pub num_reads: u64,
     pub num_bases: u64,
     pub lengths: Option<Vec<u32>>,
+    /// Records left out by the read-level filters
+    pub filtered: FilterCounts,
 }
 
-pub fn extract_read_stats(bam_path: &std::path::Path) -> Result<ReadStats, Box<dyn std::error::Error>> {
-    let mut reader = bam::io::Reader::new(BufReader::new(File::open(bam_path)?));
-    let _header = reader.read_header()?;
-    let mut lengths = Vec::new();
-    let mut quals = Vec::new();
-    let mut num_reads: u64 = 0;
-    let mut num_bases: u64 = 0;
+/// Accumulates read statistics one record at a time
+///
+/// Used both for the dedicated read-stats pass and by engines that gather
+/// statistics while reading records for coverage.
+#[derive(Default, Clone)]
+pub struct ReadStatsCollector {
+    lengths: Vec<u32>,
+    quals: Vec<f64>,
+    num_reads: u64,
+    num_bases: u64,
+    filtered: FilterCounts,
+}
 
-    for result in reader.records() {
-        let record = result?;
+impl ReadStatsCollector {
+    /// Add one alignment record to the statistics
+    pub fn add_record(&mut self, record: &dyn sam::alignment::Record) -> std::io::Result<()> {
         let len = record.sequence().len() as u32;
-        lengths.push(len);
-        num_reads += 1;
-        num_bases += len as u64;
+        self.lengths.push(len);
+        self.num_reads += 1;
+        self.num_bases += len as u64;
 
         // Collect mean quality per read (if available)
-        let qual = record.quality_scores();
-        let qual_slice = qual.as_ref();
-        if !qual_slice.is_empty() {
-            let q: f64 = qual_slice.iter().map(|&q| q as u32).sum::<u32>() as f64 / qual_slice.len() as f64;
-            quals.push(q);
+        if let Some(q) = mean_base_quality(record)? {
+            self.quals.push(q);
         }
+
+        Ok(())
     }
 
-    // N50 calculation
-    lengths.sort_unstable_by(|a, b| b.cmp(a));
-    let total: u64 = lengths.iter().map(|&l| l as u64).sum();
-    let mut acc = 0;
-    let mut n50 = 0;
-    for &l in &lengths {
-        acc += l as u64;
-        if acc >= total / 2 {
-            n50 = l;
-            break;
+    /// Add a record passing the read-level filters of `filter`, or count it as filtered
+    ///
+    /// Returns whether the record passed, so that coverage need not evaluate the filters again.
+    pub fn add_record_with_filter(&mut self, record: &dyn sam::alignment::Record, filter: &ReadFilter) -> std::io::Result<bool> {
+        match filter.check_read(record)? {
+            Some(reason) => {
+                self.filtered.add(reason);
+                Ok(false)
+            }
+            None => {
+                self.add_record(record)?;
+                Ok(true)
+            }
         }
     }
 
-    // Mean/median length
-    let mean_len = if lengths.is_empty() { 0.0 } else { lengths.iter().sum::<u32>() as f64 / lengths.len() as f64 };
-    let median_len = if lengths.is_empty() {
-        0.0
-    } else {
-        let mid = lengths.len() / 2;
-        if lengths.len() % 2 == 0 {
-            (lengths[mid - 1] + lengths[mid]) as f64 / 2.0
+    /// Add the records collected by another collector (e.g. from another chunk)
+    pub fn merge(&mut self, other: ReadStatsCollector) {
+        self.lengths.extend(other.lengths);
+        self.quals.extend(other.quals);
+        self.num_reads += other.num_reads;
+        self.num_bases += other.num_bases;
+        self.filtered.merge(other.filtered);
+    }
+
+    /// Compute N50/N75, mean/median length and quality from the collected records
+    pub fn finish(self) -> ReadStats {
+        let ReadStatsCollector { mut lengths, quals, num_reads, num_bases, filtered } = self;
+
+        // N50 calculation
+        lengths.sort_unstable_by(|a, b| b.cmp(a));
+        let total: u64 = lengths.iter().map(|&l| l as u64).sum();
+        let mut acc = 0;
+        let mut n50 = 0;
+        for &l in &lengths {
+            acc += l as u64;
+            if acc >= total / 2 {
+                n50 = l;
+                break;
+            }
+        }
+
+        // N75 calculation
+        let mut acc = 0;
+        let mut n75 = 0;
+        for &l in &lengths {
+            acc += l as u64;
+            if acc >= (total * 3) / 4 {
+                n75 = l;
+                break;
+            }
+        }
+
+        // Mean/median length
+        let mean_len = if lengths.is_empty() { 0.0 } else { lengths.iter().sum::<u32>() as f64 / lengths.len() as f64 };
+        let median_len = if lengths.is_empty() {
+            0.0
         } else {
-            lengths[mid] as f64
+            let mid = lengths.len() / 2;
+            if lengths.len() % 2 == 0 {
+                (lengths[mid - 1] + lengths[mid]) as f64 / 2.0
+            } else {
+                lengths[mid] as f64
+            }
+        };
+
+        // Mean/median quality
+        let mean_qual = if quals.is_empty() { 0.0 } else { quals.iter().sum::<f64>() / quals.len() as f64 };
+        let median_qual = if quals.is_empty() {
+            0.0
+        } else {
+            let mut sorted = quals.clone();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let mid = sorted.len() / 2;
+            if sorted.len() % 2 == 0 {
+                (sorted[mid - 1] + sorted[mid]) / 2.0
+            } else {
+                sorted[mid]
+            }
+        };
+
+        ReadStats {
+            n50,
+            n75,
+            mean_len,
+            median_len,
+            mean_qual,
+            median_qual,
+            num_reads,
+            num_bases,
+            lengths: Some(lengths),
+            filtered,
         }
-    };
+    }
+}
 
-    // Mean/median quality
-    let mean_qual = if quals.is_empty() { 0.0 } else { quals.iter().sum::<f64>() / quals.len() as f64 };
-    let median_qual = if quals.is_empty() {
-        0.0
+/// Base qualities of a record, or `None` when it has none
+///
+/// A missing QUAL (`*`) is empty in SAM records but stored as 0xFF bytes in BAM, which
+/// would otherwise read as quality 255.
+pub fn base_qualities(record: &dyn sam::alignment::Record) -> std::io::Result<Option<Vec<u8>>> {
+    let quals: Vec<u8> = record.quality_scores().iter().collect::<std::io::Result<_>>()?;
+    if quals.iter().all(|&q| q == 0xFF) {
+        Ok(None)
     } else {
-        let mut sorted = quals.clone();
-        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let mid = sorted.len() / 2;
-        if sorted.len() % 2 == 0 {
-            (sorted[mid - 1] + sorted[mid]) / 2.0
-        } else {
-            sorted[mid]
+        Ok(Some(quals))
+    }
+}
+
+/// Mean base quality of a record, or `None` without base qualities
+pub fn mean_base_quality(record: &dyn sam::alignment::Record) -> std::io::Result<Option<f64>> {
+    Ok(base_qualities(record)?.map(|quals| quals.iter().map(|&q| q as u64).sum::<u64>() as f64 / quals.len() as f64))
+}
+
+/// Read statistics combined over all inputs, for the records passing the read-level filters
+pub fn extract_read_stats(inputs: &[AlignmentInput], filter: &ReadFilter) -> Result<ReadStats, Box<dyn std::error::Error>> {
+    Ok(collect_read_stats(inputs, filter)?.finish())
+}
+
+/// Collect the records of all inputs in one pass, without computing the statistics yet
+pub fn collect_read_stats(inputs: &[AlignmentInput], filter: &ReadFilter) -> Result<ReadStatsCollector, Box<dyn std::error::Error>> {
+    let mut collector = ReadStatsCollector::default();
+
+    for input in inputs {
+        let mut reader = input.open_sequential()?;
+        let header = reader.read_header()?;
+
+        for result in reader.records(&header) {
+            let record = result?;
+            collector.add_record_with_filter(record.as_ref(), filter)?;
         }
-    };
-
-    Ok(ReadStats {
-        n50,
-        mean_len,
-        median_len,
-        mean_qual,
-        median_qual,
-        num_reads,
-        num_bases,
-        lengths: Some(lengths),
-    })
+    }
+
+    Ok(collector)
 }
 // Utility functions for nanocov
 
 // Place for helpers, region job creation, merging, etc.
 // (Implementations will be moved in the next step)
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sam::alignment::record_buf::Sequence;
+    use sam::alignment::RecordBuf;
+
+    fn collector_for(lengths: &[usize]) -> ReadStatsCollector {
+        let mut collector = ReadStatsCollector::default();
+        for &len in lengths {
+            let record = RecordBuf::builder().set_sequence(Sequence::from(vec![b'A'; len])).build();
+            collector.add_record(&record).unwrap();
+        }
+        collector
+    }
+
+    #[test]
+    fn test_merged_collectors_match_single_pass() {
+        let mut merged = collector_for(&[100, 400]);
+        merged.merge(collector_for(&[300, 200]));
+        let stats = merged.finish();
+        let expected = collector_for(&[100, 400, 300, 200]).finish();
+
+        assert_eq!(stats.num_reads, 4);
+        assert_eq!(stats.num_bases, 1000);
+        assert_eq!(stats.lengths, expected.lengths);
+        // 400 + 300 >= 500 and 400 + 300 + 200 >= 750
+        assert_eq!((stats.n50, stats.n75), (300, 200));
+    }
+
+    #[test]
+    fn test_missing_qual_is_not_quality_255() {
+        use noodles_bam as bam;
+        use sam::alignment::io::Write as _;
+        use sam::alignment::record_buf::QualityScores;
+
+        // SEQ with QUAL '*': empty as a SAM record, 0xFF bytes once written to BAM
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("noqual.bam");
+        let header = sam::Header::default();
+        let mut writer = bam::io::Writer::new(std::fs::File::create(&path).unwrap());
+        writer.write_header(&header).unwrap();
+        let no_qual = RecordBuf::builder().set_sequence(Sequence::from(b"ACGT".to_vec())).build();
+        writer.write_alignment_record(&header, &no_qual).unwrap();
+        writer.try_finish().unwrap();
+
+        let mut reader = bam::io::Reader::new(std::fs::File::open(&path).unwrap());
+        reader.read_header().unwrap();
+        let bam_record = reader.records().next().unwrap().unwrap();
+        assert!(!sam::alignment::Record::quality_scores(&bam_record).is_empty());
+
+        let mut collector = ReadStatsCollector::default();
+        collector.add_record(&no_qual).unwrap();
+        collector.add_record(&bam_record).unwrap();
+        let with_qual = RecordBuf::builder()
+            .set_sequence(Sequence::from(b"ACGT".to_vec()))
+            .set_quality_scores(QualityScores::from(vec![10, 20, 30, 40]))
+            .build();
+        collector.add_record(&with_qual).unwrap();
+
+        let stats = collector.finish();
+        assert_eq!(stats.num_reads, 3);
+        assert_eq!(stats.mean_qual, 25.0);
+    }
+}
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use nanocov::{parse_bed};
+use nanocov::{parse_bed, parse_bed_records, BedRecord};
 
 #[test]
 fn test_parse_bed_basic() {
@@ -21,3 +21,47 @@ fn test_parse_bed_ignores_comments_and_blank() {
     assert_eq!(regions["chr1"], vec![(1, 2)]);
     assert_eq!(regions["chr2"], vec![(3, 4)]);
 }
+
+#[test]
+fn test_parse_bed_skips_track_lines_and_rejects_reversed_intervals() {
+    let tmpfile = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(tmpfile.path(), "track name=targets\nbrowser position chr1:1-100\nchr1\t0\t10\tamplicon1\n").unwrap();
+    let regions = parse_bed(&PathBuf::from(tmpfile.path())).unwrap();
+    assert_eq!(regions["chr1"], vec![(0, 10)]);
+
+    std::fs::write(tmpfile.path(), "chr1\t10\t5\n").unwrap();
+    assert!(parse_bed(&PathBuf::from(tmpfile.path())).is_err());
+}
+
+#[test]
+fn test_parse_bed_keeps_contigs_starting_with_track_or_browser() {
+    let tmpfile = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(tmpfile.path(), "track	type=bed
+tracker_1	0	10
+browser_contig	5	6
+browser
+").unwrap();
+    let regions = parse_bed(&PathBuf::from(tmpfile.path())).unwrap();
+    assert_eq!(regions.len(), 2);
+    assert_eq!(regions["tracker_1"], vec![(0, 10)]);
+    assert_eq!(regions["browser_contig"], vec![(5, 6)]);
+}
+
+#[test]
+fn test_parse_bed_records_keeps_name_score_and_strand() {
+    let tmpfile = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(tmpfile.path(), "chr1\t0\t10\tamplicon1\t60\t-\t0\t10\nchr2\t5\t15\n").unwrap();
+    let records = parse_bed_records(&PathBuf::from(tmpfile.path())).unwrap();
+    assert_eq!(
+        records[0],
+        BedRecord {
+            chrom: "chr1".to_string(),
+            start: 0,
+            end: 10,
+            name: Some("amplicon1".to_string()),
+            score: Some("60".to_string()),
+            strand: Some("-".to_string()),
+        }
+    );
+    assert_eq!((records[1].start, records[1].end, records[1].name.as_deref()), (5, 15, None));
+}
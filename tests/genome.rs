@@ -0,0 +1,22 @@
+use std::path::PathBuf;
+
+use nanocov::parse_genome_file;
+
+#[test]
+fn test_parse_genome_file_reads_fai_and_genome_files() {
+    let tmpfile = tempfile::NamedTempFile::new().unwrap();
+
+    // samtools faidx index: name, length, offset, line bases, line width
+    std::fs::write(tmpfile.path(), "chr1\t248956422\t112\t70\t71\nchrM\t16569\t253105752\t70\t71\n").unwrap();
+    let lengths = parse_genome_file(&PathBuf::from(tmpfile.path())).unwrap();
+    assert_eq!(lengths, vec![("chr1".to_string(), 248956422), ("chrM".to_string(), 16569)]);
+
+    std::fs::write(tmpfile.path(), "# bedtools genome file\n1\t1000\n\n2 500\n").unwrap();
+    let lengths = parse_genome_file(&PathBuf::from(tmpfile.path())).unwrap();
+    assert_eq!(lengths, vec![("1".to_string(), 1000), ("2".to_string(), 500)]);
+
+    std::fs::write(tmpfile.path(), "chr1\n").unwrap();
+    assert!(parse_genome_file(&PathBuf::from(tmpfile.path())).is_err());
+    std::fs::write(tmpfile.path(), "chr1\tlong\n").unwrap();
+    assert!(parse_genome_file(&PathBuf::from(tmpfile.path())).is_err());
+}
@@ -0,0 +1,35 @@
+use std::collections::HashMap;
+
+use nanocov::parse_region;
+
+fn contigs() -> HashMap<String, u32> {
+    HashMap::from([
+        ("chr1".to_string(), 248_956_422),
+        ("chrX".to_string(), 156_040_895),
+        ("HLA-A*01:01:01:01".to_string(), 3_503),
+    ])
+}
+
+#[test]
+fn test_parse_region_strings() {
+    let contigs = contigs();
+    assert_eq!(parse_region("chr1:1,000,000-2,000,000", &contigs), Ok(("chr1".to_string(), 999_999, 2_000_000)));
+    assert_eq!(parse_region("chrX", &contigs), Ok(("chrX".to_string(), 0, 156_040_895)));
+    assert_eq!(parse_region("chr1:100", &contigs), Ok(("chr1".to_string(), 99, 248_956_422)));
+    assert_eq!(parse_region("chr1:100-", &contigs), Ok(("chr1".to_string(), 99, 248_956_422)));
+    assert_eq!(parse_region("chr1:5-5", &contigs), Ok(("chr1".to_string(), 4, 5)));
+    // Contig names with colons
+    assert_eq!(parse_region("HLA-A*01:01:01:01", &contigs), Ok(("HLA-A*01:01:01:01".to_string(), 0, 3_503)));
+    assert_eq!(parse_region("HLA-A*01:01:01:01:1-100", &contigs), Ok(("HLA-A*01:01:01:01".to_string(), 0, 100)));
+}
+
+#[test]
+fn test_parse_region_rejects_unknown_contigs_and_bad_ranges() {
+    let contigs = contigs();
+    assert!(parse_region("1:1-100", &contigs).unwrap_err().contains("unknown contig '1'"));
+    assert!(parse_region("chrM", &contigs).unwrap_err().contains("unknown contig 'chrM'"));
+    assert!(parse_region("chrX:0-10", &contigs).unwrap_err().contains("1-based"));
+    assert!(parse_region("chrX:200-100", &contigs).unwrap_err().contains("ends before it starts"));
+    assert!(parse_region("chrX:1-200,000,000", &contigs).unwrap_err().contains("past the end of chrX"));
+    assert!(parse_region("chrX:1-2x", &contigs).unwrap_err().contains("invalid position '2x'"));
+}